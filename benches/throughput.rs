@@ -0,0 +1,233 @@
+// End-to-end throughput of the HTTP proxy path. Starts a few in-process
+// backends, runs the load balancer binary in front of them and measures
+// requests per second at several concurrency levels.
+//
+//   cargo bench --bench throughput
+//
+// Settings, all optional:
+//   LB_BENCH_SECONDS      seconds per concurrency level (default 5)
+//   LB_BENCH_CONCURRENCY  comma-separated levels (default 1,8,32,128)
+//   LB_BENCH_STRATEGY     roundrobin, weighted or sticky (default roundrobin)
+//   LB_BENCH_CLIENTS      distinct client addresses for sticky (default 10000)
+//   LB_BENCH_BINARY       load balancer binary to measure instead of this
+//                         build's, e.g. one built from an older commit
+//   LB_BENCH_WORKERS      comma-separated Tokio worker thread counts for the
+//                         balancer, each measured in turn (default: Tokio's
+//                         default, one per core)
+
+use std::convert::Infallible;
+use std::env;
+use std::net::{SocketAddr, TcpListener};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Client, Request, Response, Server};
+
+const BACKENDS: usize = 4;
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("no free port")
+}
+
+fn start_backend() -> SocketAddr {
+    let make_service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|_req| async {
+            Ok::<_, Infallible>(Response::new(Body::from("ok")))
+        }))
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+// Kills the load balancer when the benchmark ends, even on a panic
+struct Balancer(Child);
+
+impl Drop for Balancer {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn start_balancer(
+    listen: SocketAddr,
+    backends: &[SocketAddr],
+    strategy: &str,
+    workers: Option<usize>,
+) -> Balancer {
+    let backends: Vec<_> = backends
+        .iter()
+        .enumerate()
+        .map(|(i, addr)| serde_json::json!({ "url": format!("http://{}", addr), "weight": i + 1 }))
+        .collect();
+    let config = serde_json::json!({
+        "listen_address": listen.to_string(),
+        "strategy": strategy,
+        "backends": backends,
+        "health_check": { "path": "/", "interval_seconds": 3600, "timeout_seconds": 1, "max_failures": 3 },
+        "session": { "timeout_seconds": 3600, "cookie_name": "lb" },
+    });
+
+    let path = env::temp_dir().join(format!("lb-bench-{}.json", std::process::id()));
+    std::fs::write(&path, config.to_string()).expect("failed to write the bench config");
+
+    let binary = env::var("LB_BENCH_BINARY")
+        .unwrap_or_else(|_| env!("CARGO_BIN_EXE_load-balancer").to_string());
+    let mut command = Command::new(&binary);
+    if let Some(workers) = workers {
+        command.env("TOKIO_WORKER_THREADS", workers.to_string());
+    }
+    let child = command
+        .arg("--config")
+        .arg(&path)
+        .arg("--log-level=error")
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap_or_else(|e| panic!("failed to start {}: {}", binary, e));
+    Balancer(child)
+}
+
+async fn wait_until_listening(addr: SocketAddr) {
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(addr).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("load balancer did not start listening on {}", addr);
+}
+
+// Runs `concurrency` request loops for `duration`; returns requests per
+// second and the number of failed requests
+async fn run_level(
+    target: SocketAddr,
+    concurrency: usize,
+    duration: Duration,
+    clients: Option<u32>,
+) -> (f64, u64) {
+    let client = Client::new();
+    let done = Arc::new(AtomicBool::new(false));
+    let completed = Arc::new(AtomicU64::new(0));
+    let failed = Arc::new(AtomicU64::new(0));
+    let uri: hyper::Uri = format!("http://{}/bench", target).parse().unwrap();
+
+    let tasks: Vec<_> = (0..concurrency)
+        .map(|_| {
+            let client = client.clone();
+            let done = done.clone();
+            let completed = completed.clone();
+            let failed = failed.clone();
+            let uri = uri.clone();
+            tokio::spawn(async move {
+                while !done.load(Ordering::Relaxed) {
+                    let mut req = Request::get(uri.clone());
+                    if let Some(clients) = clients {
+                        let n = fastrand::u32(..clients);
+                        req = req.header(
+                            "X-Forwarded-For",
+                            format!("10.{}.{}.{}", n >> 16, (n >> 8) & 0xff, n & 0xff),
+                        );
+                    }
+                    let ok = match client.request(req.body(Body::empty()).unwrap()).await {
+                        Ok(response) => {
+                            let ok = response.status().is_success();
+                            ok && hyper::body::to_bytes(response.into_body()).await.is_ok()
+                        }
+                        Err(_) => false,
+                    };
+                    if ok {
+                        completed.fetch_add(1, Ordering::Relaxed);
+                    } else {
+                        failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let started = Instant::now();
+    tokio::time::sleep(duration).await;
+    done.store(true, Ordering::Relaxed);
+    let requests = completed.load(Ordering::Relaxed);
+    let elapsed = started.elapsed();
+    for task in tasks {
+        let _ = task.await;
+    }
+
+    (
+        requests as f64 / elapsed.as_secs_f64(),
+        failed.load(Ordering::Relaxed),
+    )
+}
+
+#[tokio::main]
+async fn main() {
+    // `cargo test --benches` runs this with --bench absent; nothing to check
+    if !env::args().any(|arg| arg == "--bench") {
+        return;
+    }
+
+    let seconds: u64 = env_or("LB_BENCH_SECONDS", 5);
+    let strategy: String = env_or("LB_BENCH_STRATEGY", "roundrobin".to_string());
+    let levels: Vec<usize> = env_or("LB_BENCH_CONCURRENCY", "1,8,32,128".to_string())
+        .split(',')
+        .filter_map(|level| level.trim().parse().ok())
+        .collect();
+    let clients = (strategy == "sticky").then(|| env_or("LB_BENCH_CLIENTS", 10_000u32));
+
+    let workers: Vec<Option<usize>> = match env::var("LB_BENCH_WORKERS") {
+        Ok(counts) => counts
+            .split(',')
+            .filter_map(|count| count.trim().parse().ok())
+            .map(Some)
+            .collect(),
+        Err(_) => vec![None],
+    };
+
+    let backends: Vec<SocketAddr> = (0..BACKENDS).map(|_| start_backend()).collect();
+
+    println!(
+        "strategy {}, {} backends, {}s per level, {} cores",
+        strategy,
+        BACKENDS,
+        seconds,
+        std::thread::available_parallelism().map_or(1, |n| n.get())
+    );
+    println!(
+        "{:>8} {:>12} {:>12} {:>8}",
+        "workers", "concurrency", "req/s", "errors"
+    );
+    for workers in workers {
+        let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+        let _balancer = start_balancer(listen, &backends, &strategy, workers);
+        wait_until_listening(listen).await;
+
+        // Warms up connections and, for sticky, the session map
+        run_level(listen, 8, Duration::from_secs(1), clients).await;
+
+        let label = workers.map_or("default".to_string(), |n| n.to_string());
+        for &concurrency in &levels {
+            let (rate, errors) =
+                run_level(listen, concurrency, Duration::from_secs(seconds), clients).await;
+            println!(
+                "{:>8} {:>12} {:>12.0} {:>8}",
+                label, concurrency, rate, errors
+            );
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use std::convert::Infallible;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::sync::Arc;
+
+use hyper::body::Body;
+use hyper::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use log::{error, info, warn};
+use subtle::ConstantTimeEq;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::audit::AuditLog;
+use crate::config::{AdminConfig, AdminTlsConfig, AdminTokenConfig};
+use crate::load_balancer::LoadBalancer;
+use crate::load_balancer::admin::{self, AdminContext};
+use crate::router::SharedRouter;
+use crate::tls;
+
+// Serves the admin endpoints on their own address (TCP or Unix socket),
+// away from the public listener. Callers authenticate with a bearer token
+// or, when a client CA is configured, a client certificate.
+pub struct AdminServer {
+    listen_address: String,
+    tokens: Vec<AdminTokenConfig>,
+    acceptor: Option<TlsAcceptor>,
+    audit: AuditLog,
+    lb: Arc<LoadBalancer>,
+    router: Arc<SharedRouter>,
+}
+
+impl AdminServer {
+    pub fn new(
+        config: &AdminConfig,
+        lb: Arc<LoadBalancer>,
+        router: Arc<SharedRouter>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mtls = config
+            .tls
+            .as_ref()
+            .is_some_and(|tls| tls.client_ca_path.is_some());
+        if config.tokens.is_empty() && !mtls {
+            return Err(
+                "The admin listener needs at least one token or a tls.client_ca_path".into(),
+            );
+        }
+        if let Some(token) = config.tokens.iter().find(|t| t.token.is_empty()) {
+            return Err(format!("Admin token '{}' is empty", token.name).into());
+        }
+
+        let acceptor = match &config.tls {
+            Some(tls_config) => Some(TlsAcceptor::from(load_tls_config(tls_config)?)),
+            None => None,
+        };
+
+        Ok(AdminServer {
+            listen_address: config.listen_address.clone(),
+            tokens: config.tokens.clone(),
+            acceptor,
+            audit: AuditLog::open(config.audit_log.as_deref())?,
+            lb,
+            router,
+        })
+    }
+
+    pub async fn serve(self) -> Result<(), Box<dyn std::error::Error>> {
+        let server = Arc::new(self);
+
+        if let Some(path) = server.listen_address.strip_prefix("unix:") {
+            let listener = bind_unix_socket(Path::new(path))?;
+            info!("Starting admin listener on unix:{}", path);
+
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(server.clone().accept(stream, "unix socket".to_string()));
+                    }
+                    Err(e) => error!("Failed to accept admin connection: {}", e),
+                }
+            }
+        }
+
+        let addr: SocketAddr = server.listen_address.parse()?;
+        let listener = TcpListener::bind(addr).await?;
+        info!("Starting admin listener on {}", addr);
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, remote_addr)) => {
+                    tokio::spawn(server.clone().accept(stream, remote_addr.ip().to_string()));
+                }
+                Err(e) => error!("Failed to accept admin connection: {}", e),
+            }
+        }
+    }
+
+    async fn accept<S>(self: Arc<Self>, stream: S, peer: String)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let Some(acceptor) = self.acceptor.clone() else {
+            self.serve_connection(stream, peer, None).await;
+            return;
+        };
+
+        let stream = match acceptor.accept(stream).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Admin TLS handshake with {} failed: {}", peer, e);
+                return;
+            }
+        };
+
+        // Only present when the certificate was verified against the client CA
+        let cert_name = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .map(|cert| {
+                subject_common_name(cert).unwrap_or_else(|| "client certificate".to_string())
+            });
+
+        self.serve_connection(stream, peer, cert_name).await;
+    }
+
+    async fn serve_connection<S>(
+        self: Arc<Self>,
+        stream: S,
+        peer: String,
+        cert_name: Option<String>,
+    ) where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let service_peer = peer.clone();
+        let service = service_fn(move |req| {
+            let server = self.clone();
+            let peer = service_peer.clone();
+            let cert_name = cert_name.clone();
+            async move { Ok::<_, Infallible>(server.handle(req, &peer, cert_name.as_deref()).await) }
+        });
+
+        if let Err(e) = Http::new()
+            .http1_only(true)
+            .serve_connection(stream, service)
+            .await
+        {
+            warn!("Error serving admin connection from {}: {}", peer, e);
+        }
+    }
+
+    async fn handle(
+        &self,
+        req: Request<Body>,
+        peer: &str,
+        cert_name: Option<&str>,
+    ) -> Response<Body> {
+        let Some(principal) = self.authenticate(&req, cert_name) else {
+            warn!(
+                "Rejected unauthenticated admin request {} {} from {}",
+                req.method(),
+                req.uri().path(),
+                peer
+            );
+            return Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .header(WWW_AUTHENTICATE, "Bearer")
+                .body(Body::from("Unauthorized"))
+                .unwrap();
+        };
+
+        if !req.uri().path().starts_with("/admin/") {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("Not found"))
+                .unwrap();
+        }
+
+        let who = format!("{} ({})", principal, peer);
+        let router = self.router.load();
+        let ctx = AdminContext {
+            lb: &self.lb,
+            router: &router,
+            audit: &self.audit,
+            who: &who,
+        };
+        admin::handle(req, &ctx).await
+    }
+
+    // A bearer token, when sent, must be valid and names the caller;
+    // otherwise a verified client certificate does
+    fn authenticate(&self, req: &Request<Body>, cert_name: Option<&str>) -> Option<String> {
+        let Some(value) = req.headers().get(AUTHORIZATION) else {
+            return cert_name.map(|name| format!("cert:{}", name));
+        };
+
+        let token = value.to_str().ok()?.strip_prefix("Bearer ")?.trim();
+        self.tokens
+            .iter()
+            .find(|t| bool::from(t.token.as_bytes().ct_eq(token.as_bytes())))
+            .map(|t| t.name.clone())
+    }
+}
+
+// Binds the socket under a private directory and moves it into place once
+// it is 0600, so it is never reachable with looser permissions. Only a
+// socket left behind by a previous run is replaced, never another file.
+fn bind_unix_socket(path: &Path) -> Result<UnixListener, Box<dyn std::error::Error>> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(format!(
+                "{} exists and is not a socket; refusing to replace it",
+                path.display()
+            )
+            .into());
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let parent = path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let private_dir = parent.join(format!(".admin-socket-{}", std::process::id()));
+    fs::DirBuilder::new().mode(0o700).create(&private_dir)?;
+
+    let staged = private_dir.join("socket");
+    let result = UnixListener::bind(&staged)
+        .map_err(Into::into)
+        .and_then(|listener| {
+            fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+            fs::rename(&staged, path)?;
+            Ok(listener)
+        });
+    let _ = fs::remove_file(&staged);
+    let _ = fs::remove_dir(&private_dir);
+
+    result
+}
+
+fn load_tls_config(
+    config: &AdminTlsConfig,
+) -> Result<Arc<ServerConfig>, Box<dyn std::error::Error>> {
+    let (certs, key) = tls::load_cert_and_key(&config.cert_path, &config.key_path)?;
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = match &config.client_ca_path {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in rustls_pemfile::certs(&mut BufReader::new(File::open(ca_path)?)) {
+                roots.add(cert?)?;
+            }
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = builder.with_single_cert(certs, key)?;
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Arc::new(server_config))
+}
+
+// Common name of an X.509 certificate's subject, used to name mTLS callers
+// in the audit log
+fn subject_common_name(cert: &[u8]) -> Option<String> {
+    let (_, cert) = X509Certificate::from_der(cert).ok()?;
+    let common_name = cert.subject().iter_common_name().next()?;
+    common_name.as_str().ok().map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_name_of_v3_certificate() {
+        let cert = include_bytes!("../testdata/certs/v3-cn.der");
+        assert_eq!(subject_common_name(cert).as_deref(), Some("deploy-bot"));
+    }
+
+    #[test]
+    fn common_name_of_v1_certificate() {
+        let cert = include_bytes!("../testdata/certs/v1-cn.der");
+        assert_eq!(subject_common_name(cert).as_deref(), Some("deploy-bot"));
+    }
+
+    #[test]
+    fn subject_without_common_name() {
+        assert_eq!(
+            subject_common_name(include_bytes!("../testdata/certs/v3-no-cn.der")),
+            None
+        );
+        assert_eq!(
+            subject_common_name(include_bytes!("../testdata/certs/v1-no-cn.der")),
+            None
+        );
+    }
+
+    #[test]
+    fn malformed_certificates() {
+        let cert = include_bytes!("../testdata/certs/v3-cn.der");
+        assert_eq!(subject_common_name(&[]), None);
+        assert_eq!(subject_common_name(&cert[..cert.len() / 2]), None);
+        assert_eq!(
+            subject_common_name(&[0x30, 0x84, 0xff, 0xff, 0xff, 0xff]),
+            None
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use serde_json::{Value, json};
+
+// Record of every change made through the admin interface: who made it,
+// what was changed, and the values before and after. Entries always go to
+// the log under the "audit" target, and to a JSON-lines file if configured.
+#[derive(Default)]
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+}
+
+impl AuditLog {
+    pub fn open(path: Option<&str>) -> io::Result<Self> {
+        let file = match path {
+            Some(path) => Some(Mutex::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+            None => None,
+        };
+
+        Ok(AuditLog { file })
+    }
+
+    pub fn record(&self, who: &str, action: &str, old: Value, new: Value) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64());
+        let entry = json!({
+            "time": time,
+            "who": who,
+            "action": action,
+            "old": old,
+            "new": new,
+        });
+
+        info!(target: "audit", "{}", entry);
+
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+            if let Err(e) = writeln!(file, "{}", entry) {
+                warn!("Failed to write audit log entry: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use log::LevelFilter;
+
+use crate::config::{ConfigFormat, ListenerMode, LoadBalancerConfig, Strategy};
+
+pub const DEFAULT_CONFIG_PATH: &str = "config.json";
+
+pub const USAGE: &str = "\
+Usage: load-balancer [OPTIONS]
+
+Options:
+  -c, --config <PATH>         Config file to load [env: LB_CONFIG] [default: config.json]
+      --check                 Validate the config and exit
+      --print-default-config[=FORMAT]
+                              Print the default config as json, yaml or toml and exit
+  -l, --listen <ADDR>         Override listen_address [env: LB_LISTEN_ADDRESS]
+      --mode <MODE>           Override mode: http, tcp or udp [env: LB_MODE]
+      --strategy <STRATEGY>   Override strategy: roundrobin, weighted or sticky [env: LB_STRATEGY]
+      --zone <ZONE>           Override zone [env: LB_ZONE]
+      --admin-listen <ADDR>   Override admin.listen_address [env: LB_ADMIN_LISTEN_ADDRESS]
+      --log-level <LEVEL>     off, error, warn, info, debug or trace [env: RUST_LOG]
+  -v, --verbose               Same as --log-level debug
+  -q, --quiet                 Same as --log-level warn
+  -h, --help                  Print this help
+  -V, --version               Print the version
+";
+
+// Config values set from the command line or environment, which take
+// precedence over the file. Kept so they also apply on reload.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    pub listen_address: Option<String>,
+    pub mode: Option<ListenerMode>,
+    pub strategy: Option<Strategy>,
+    pub zone: Option<String>,
+    pub admin_listen_address: Option<String>,
+}
+
+impl Overrides {
+    pub fn apply(&self, config: &mut LoadBalancerConfig) -> Result<(), String> {
+        if let Some(listen_address) = &self.listen_address {
+            config.listen_address = listen_address.clone();
+        }
+        if let Some(mode) = self.mode {
+            config.mode = mode;
+        }
+        if let Some(strategy) = &self.strategy {
+            config.strategy = strategy.clone();
+        }
+        if let Some(zone) = &self.zone {
+            config.zone = Some(zone.clone());
+        }
+        if let Some(admin_listen_address) = &self.admin_listen_address {
+            let admin = config.admin.as_mut().ok_or(
+                "The admin listen address was overridden, but the config has no `admin` section",
+            )?;
+            admin.listen_address = admin_listen_address.clone();
+        }
+        Ok(())
+    }
+
+    // Flags win over environment variables
+    fn fill_from_env(&mut self) -> Result<(), String> {
+        if self.listen_address.is_none() {
+            self.listen_address = env_var("LB_LISTEN_ADDRESS");
+        }
+        if self.mode.is_none()
+            && let Some(mode) = env_var("LB_MODE")
+        {
+            self.mode = Some(parse_mode(&mode).map_err(|e| format!("LB_MODE: {}", e))?);
+        }
+        if self.strategy.is_none()
+            && let Some(strategy) = env_var("LB_STRATEGY")
+        {
+            self.strategy =
+                Some(parse_strategy(&strategy).map_err(|e| format!("LB_STRATEGY: {}", e))?);
+        }
+        if self.zone.is_none() {
+            self.zone = env_var("LB_ZONE");
+        }
+        if self.admin_listen_address.is_none() {
+            self.admin_listen_address = env_var("LB_ADMIN_LISTEN_ADDRESS");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Run,
+    Check,
+    PrintDefaultConfig(ConfigFormat),
+    Help,
+    Version,
+}
+
+#[derive(Debug)]
+pub struct Cli {
+    pub command: Command,
+    // None when neither --config nor LB_CONFIG was given
+    pub config_path: Option<PathBuf>,
+    pub log_level: Option<LevelFilter>,
+    pub overrides: Overrides,
+}
+
+impl Cli {
+    pub fn parse() -> Result<Self, String> {
+        Self::parse_from(env::args().skip(1))
+    }
+
+    fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut cli = Cli {
+            command: Command::Run,
+            config_path: None,
+            log_level: None,
+            overrides: Overrides::default(),
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // Both `--flag value` and `--flag=value` are accepted
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value)),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || match inline_value {
+                Some(value) => Ok(value.to_string()),
+                None => args.next().ok_or_else(|| format!("{} needs a value", flag)),
+            };
+
+            match flag.as_str() {
+                "-c" | "--config" => cli.config_path = Some(PathBuf::from(value()?)),
+                "--check" => cli.command = Command::Check,
+                "--print-default-config" => {
+                    let format = match inline_value {
+                        None | Some("json") => ConfigFormat::Json,
+                        Some("yaml") => ConfigFormat::Yaml,
+                        Some("toml") => ConfigFormat::Toml,
+                        Some(other) => return Err(format!("Unknown config format '{}'", other)),
+                    };
+                    cli.command = Command::PrintDefaultConfig(format);
+                }
+                "-l" | "--listen" => cli.overrides.listen_address = Some(value()?),
+                "--mode" => cli.overrides.mode = Some(parse_mode(&value()?)?),
+                "--strategy" => cli.overrides.strategy = Some(parse_strategy(&value()?)?),
+                "--zone" => cli.overrides.zone = Some(value()?),
+                "--admin-listen" => cli.overrides.admin_listen_address = Some(value()?),
+                "--log-level" => {
+                    let level = value()?;
+                    cli.log_level = Some(
+                        level
+                            .parse()
+                            .map_err(|_| format!("Unknown log level '{}'", level))?,
+                    );
+                }
+                "-v" | "--verbose" => cli.log_level = Some(LevelFilter::Debug),
+                "-q" | "--quiet" => cli.log_level = Some(LevelFilter::Warn),
+                "-h" | "--help" => cli.command = Command::Help,
+                "-V" | "--version" => cli.command = Command::Version,
+                _ => return Err(format!("Unknown argument '{}'", arg)),
+            }
+        }
+
+        if cli.config_path.is_none() {
+            cli.config_path = env_var("LB_CONFIG").map(PathBuf::from);
+        }
+        cli.overrides.fill_from_env()?;
+
+        Ok(cli)
+    }
+}
+
+// Reads the config file, applies the overrides on top and validates the result
+pub fn load_config(path: &Path, overrides: &Overrides) -> Result<LoadBalancerConfig, String> {
+    let mut config =
+        LoadBalancerConfig::from_file(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    overrides.apply(&mut config)?;
+    config
+        .validate()
+        .map_err(|errors| format!("{}: {}", path.display(), errors))?;
+    Ok(config)
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn parse_mode(value: &str) -> Result<ListenerMode, String> {
+    match value {
+        "http" => Ok(ListenerMode::Http),
+        "tcp" => Ok(ListenerMode::Tcp),
+        "udp" => Ok(ListenerMode::Udp),
+        _ => Err(format!("Unknown mode '{}'", value)),
+    }
+}
+
+fn parse_strategy(value: &str) -> Result<Strategy, String> {
+    match value {
+        "roundrobin" => Ok(Strategy::RoundRobin),
+        "weighted" => Ok(Strategy::WeightedRoundRobin),
+        "sticky" => Ok(Strategy::StickySession),
+        _ => Err(format!("Unknown strategy '{}'", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::parse_from(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn runs_by_default() {
+        let cli = parse(&[]).unwrap();
+        assert_eq!(cli.command, Command::Run);
+        assert_eq!(cli.log_level, None);
+    }
+
+    #[test]
+    fn accepts_separate_and_inline_values() {
+        let cli = parse(&["-c", "lb.yaml", "--check"]).unwrap();
+        assert_eq!(cli.command, Command::Check);
+        assert_eq!(cli.config_path, Some(PathBuf::from("lb.yaml")));
+
+        let cli = parse(&["--config=lb.toml", "--listen=0.0.0.0:80"]).unwrap();
+        assert_eq!(cli.config_path, Some(PathBuf::from("lb.toml")));
+        assert_eq!(cli.overrides.listen_address.as_deref(), Some("0.0.0.0:80"));
+    }
+
+    #[test]
+    fn parses_overrides() {
+        let cli = parse(&[
+            "--mode",
+            "udp",
+            "--strategy",
+            "weighted",
+            "--zone",
+            "eu-1",
+            "--admin-listen",
+            "unix:/run/lb.sock",
+        ])
+        .unwrap();
+        assert_eq!(cli.overrides.mode, Some(ListenerMode::Udp));
+        assert_eq!(cli.overrides.strategy, Some(Strategy::WeightedRoundRobin));
+        assert_eq!(cli.overrides.zone.as_deref(), Some("eu-1"));
+        assert_eq!(
+            cli.overrides.admin_listen_address.as_deref(),
+            Some("unix:/run/lb.sock")
+        );
+    }
+
+    #[test]
+    fn parses_default_config_format() {
+        let format = |args: &[&str]| parse(args).unwrap().command;
+        assert_eq!(
+            format(&["--print-default-config"]),
+            Command::PrintDefaultConfig(ConfigFormat::Json)
+        );
+        assert_eq!(
+            format(&["--print-default-config=yaml"]),
+            Command::PrintDefaultConfig(ConfigFormat::Yaml)
+        );
+        assert!(parse(&["--print-default-config=xml"]).is_err());
+    }
+
+    #[test]
+    fn parses_log_level() {
+        let level = |args: &[&str]| parse(args).unwrap().log_level;
+        assert_eq!(level(&["--log-level", "trace"]), Some(LevelFilter::Trace));
+        assert_eq!(level(&["-v"]), Some(LevelFilter::Debug));
+        assert_eq!(level(&["-q"]), Some(LevelFilter::Warn));
+        // The last flag wins
+        assert_eq!(level(&["-v", "-q"]), Some(LevelFilter::Warn));
+        assert!(parse(&["--log-level", "loud"]).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse(&["--bogus"]).is_err());
+        assert!(parse(&["--config"]).is_err());
+        assert!(parse(&["--mode", "sctp"]).is_err());
+        assert!(parse(&["--strategy", "random"]).is_err());
+        // Only long flags take inline values
+        assert!(parse(&["-c=lb.json"]).is_err());
+    }
+
+    #[test]
+    fn admin_override_needs_admin_section() {
+        let overrides = Overrides {
+            admin_listen_address: Some("127.0.0.1:9090".to_string()),
+            ..Default::default()
+        };
+        let mut config = LoadBalancerConfig::default();
+        assert!(overrides.apply(&mut config).is_err());
+    }
+}
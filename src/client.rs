@@ -0,0 +1,84 @@
+use std::sync::OnceLock;
+
+use hyper::Client;
+use hyper::client::HttpConnector;
+use hyper_rustls::builderstates::WantsSchemes;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use log::error;
+
+use crate::config::BackendProtocol;
+
+pub type HttpsClient = Client<HttpsConnector<HttpConnector>>;
+
+// One pooled client per backend protocol, so HTTP/1.1, h2 and h2c
+// connections are never mixed in the same pool.
+#[derive(Clone)]
+pub struct BackendClients {
+    http1: HttpsClient,
+    http2: HttpsClient,
+    h2c: HttpsClient,
+}
+
+// Whether the system has CA certificates. Minimal images may have none,
+// and hyper-rustls panics on an empty store.
+fn has_native_roots() -> bool {
+    match rustls_native_certs::load_native_certs() {
+        Ok(certs) if !certs.is_empty() => true,
+        Ok(_) => {
+            error!(
+                "No system CA certificates found; HTTPS backends are verified with bundled roots"
+            );
+            false
+        }
+        Err(e) => {
+            error!(
+                "Failed to load system CA certificates: {}; HTTPS backends are verified with bundled roots",
+                e
+            );
+            false
+        }
+    }
+}
+
+// Trusts the system's CA certificates, or the bundled Mozilla roots when
+// there are none
+fn connector() -> HttpsConnectorBuilder<WantsSchemes> {
+    static NATIVE_ROOTS: OnceLock<bool> = OnceLock::new();
+    if *NATIVE_ROOTS.get_or_init(has_native_roots) {
+        HttpsConnectorBuilder::new().with_native_roots()
+    } else {
+        HttpsConnectorBuilder::new().with_webpki_roots()
+    }
+}
+
+impl BackendClients {
+    pub fn new() -> Self {
+        let http1 = Client::builder().build(connector().https_or_http().enable_http1().build());
+
+        // ALPN offers only h2, so a TLS backend that can't speak it fails loudly
+        let http2 = Client::builder()
+            .http2_only(true)
+            .build(connector().https_or_http().enable_http2().build());
+
+        // Prior knowledge: plain TCP connections start with the HTTP/2 preface
+        let h2c = Client::builder()
+            .http2_only(true)
+            .build(connector().https_or_http().enable_http1().build());
+
+        BackendClients { http1, http2, h2c }
+    }
+
+    pub fn get(&self, protocol: BackendProtocol) -> &HttpsClient {
+        match protocol {
+            BackendProtocol::Http1 => &self.http1,
+            BackendProtocol::Http2 => &self.http2,
+            BackendProtocol::H2c => &self.h2c,
+        }
+    }
+}
+
+impl Default for BackendClients {
+    fn default() -> Self {
+        Self::new()
+    }
+}
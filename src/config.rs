@@ -0,0 +1,1263 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::backend_url::BackendUrl;
+use crate::load_balancer::DEFAULT_POOL;
+use crate::mirror::Mirror;
+use crate::rewrite::{HeaderRewrite, PathRewrite};
+use crate::router;
+use crate::split::Split;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Strategy {
+    #[serde(rename = "roundrobin")]
+    RoundRobin,
+    #[serde(rename = "weighted")]
+    WeightedRoundRobin,
+    #[serde(rename = "sticky")]
+    StickySession,
+}
+
+// Protocol spoken to a backend. `Http2` negotiates h2 via ALPN over TLS,
+// `H2c` uses HTTP/2 with prior knowledge over plain TCP.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum BackendProtocol {
+    #[default]
+    #[serde(rename = "http1")]
+    Http1,
+    #[serde(rename = "http2")]
+    Http2,
+    #[serde(rename = "h2c")]
+    H2c,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackendConfig {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+    #[serde(default)]
+    pub protocol: BackendProtocol,
+    // Failover tier; 0 is preferred, higher tiers only take spill-over
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: u32,
+    // Zone or locality label, compared with the balancer's own `zone`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+// How much headroom a tier is assumed to have: with 1.4, a tier keeps all
+// its traffic until fewer than 1/1.4 (~71%) of its backends are healthy
+fn default_overprovisioning_factor() -> f64 {
+    1.4
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum HealthCheckType {
+    #[default]
+    #[serde(rename = "http")]
+    Http,
+    // grpc.health.v1.Health/Check; requires an h2c or http2 backend
+    #[serde(rename = "grpc")]
+    Grpc,
+    // Plain TCP connect, for backends behind the TCP proxy
+    #[serde(rename = "tcp")]
+    Tcp,
+    // No probe, e.g. for UDP backends: every round marks all backends
+    // healthy again, so one taken out by failed traffic is back after
+    // interval_seconds
+    #[serde(rename = "none")]
+    None,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HealthCheckConfig {
+    #[serde(default, rename = "type")]
+    pub check_type: HealthCheckType,
+    pub path: String,
+    pub interval_seconds: u64,
+    pub timeout_seconds: u64,
+    pub max_failures: u32,
+    // Service name sent in the gRPC HealthCheckRequest ("" = whole server)
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub grpc_service: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionConfig {
+    pub timeout_seconds: u64,
+    pub cookie_name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum SlowStartCurve {
+    #[default]
+    #[serde(rename = "linear")]
+    Linear,
+    #[serde(rename = "exponential")]
+    Exponential,
+}
+
+fn default_slow_start_min_weight_percent() -> f64 {
+    10.0
+}
+
+// Ramps a recovered backend's weight up over `window_seconds` instead of
+// giving it its full share at once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SlowStartConfig {
+    pub window_seconds: u64,
+    #[serde(default = "default_slow_start_min_weight_percent")]
+    pub min_weight_percent: f64,
+    #[serde(default)]
+    pub curve: SlowStartCurve,
+}
+
+// A named group of backends with its own strategy and health check
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PoolConfig {
+    pub strategy: Strategy,
+    pub backends: Vec<BackendConfig>,
+    #[serde(default = "default_overprovisioning_factor")]
+    pub overprovisioning_factor: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slow_start: Option<SlowStartConfig>,
+    // Falls back to the top-level health_check when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheckConfig>,
+}
+
+// Conditions a request must meet for a route to apply; all that are set must match
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteMatch {
+    // Exact host or a wildcard such as "*.example.com"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_regex: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub methods: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub query: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegexRewriteConfig {
+    pub pattern: String,
+    // May refer to capture groups as $1 or ${name}
+    pub replacement: String,
+}
+
+// Path changes applied before forwarding, in order: strip_prefix, regex, add_prefix
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PathRewriteConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strip_prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<RegexRewriteConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub add_prefix: Option<String>,
+}
+
+// Header changes applied in order: remove, set, add. Values may use
+// $client_ip, $request_id and $backend.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeaderRewriteConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub set: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub add: HashMap<String, String>,
+}
+
+impl HeaderRewriteConfig {
+    pub fn is_empty(&self) -> bool {
+        self.remove.is_empty() && self.set.is_empty() && self.add.is_empty()
+    }
+}
+
+// A step in a route's filter chain. Filters run in the order listed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", deny_unknown_fields)]
+pub enum FilterConfig {
+    // Requires a bearer token; `name` identifies the caller in access logs
+    #[serde(rename = "auth")]
+    Auth { tokens: Vec<RouteTokenConfig> },
+    // Per client address; `burst` defaults to one second's worth
+    #[serde(rename = "rate_limit")]
+    RateLimit {
+        requests_per_second: f64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        burst: Option<u32>,
+    },
+    #[serde(rename = "headers")]
+    Headers {
+        #[serde(default)]
+        request: Box<HeaderRewriteConfig>,
+        #[serde(default)]
+        response: Box<HeaderRewriteConfig>,
+    },
+    #[serde(rename = "access_log")]
+    AccessLog {},
+}
+
+// A bearer token accepted by a route's auth filter; `name` identifies the
+// caller in the access log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteTokenConfig {
+    pub name: String,
+    pub token: String,
+}
+
+impl FilterConfig {
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            FilterConfig::Auth { tokens } if tokens.is_empty() => {
+                Err("an auth filter needs at least one token".to_string())
+            }
+            FilterConfig::RateLimit {
+                requests_per_second,
+                ..
+            } if !requests_per_second.is_finite() || *requests_per_second <= 0.0 => Err(format!(
+                "requests_per_second must be above 0, got {}",
+                requests_per_second
+            )),
+            FilterConfig::RateLimit { burst: Some(0), .. } => {
+                Err("burst must be at least 1".to_string())
+            }
+            FilterConfig::Headers { request, response } => {
+                HeaderRewrite::compile(request)?;
+                HeaderRewrite::compile(response)?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn default_redirect_status() -> u16 {
+    302
+}
+
+// Location may use $host, $path, $query and $request_uri
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedirectConfig {
+    pub location: String,
+    #[serde(default = "default_redirect_status")]
+    pub status: u16,
+}
+
+fn default_direct_status() -> u16 {
+    200
+}
+
+// A fixed response served without contacting any backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DirectResponseConfig {
+    #[serde(default = "default_direct_status")]
+    pub status: u16,
+    #[serde(default)]
+    pub body: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+}
+
+fn default_mirror_percentage() -> f64 {
+    100.0
+}
+
+fn default_mirror_max_in_flight() -> usize {
+    100
+}
+
+fn default_mirror_timeout_seconds() -> u64 {
+    5
+}
+
+// Copies a share of a route's requests to a shadow pool; responses are discarded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MirrorConfig {
+    pub pool: String,
+    #[serde(default = "default_mirror_percentage")]
+    pub percentage: f64,
+    // Mirrored requests still running beyond this many are not started
+    #[serde(default = "default_mirror_max_in_flight")]
+    pub max_in_flight: usize,
+    #[serde(default = "default_mirror_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SplitConfig {
+    pub pool: String,
+    pub weight: u32,
+}
+
+// Lets a request pick a split pool by naming it in a header or cookie
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SplitOverrideConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cookie: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    // Higher priorities are evaluated first; ties keep config order
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default, rename = "match")]
+    pub matches: RouteMatch,
+    // Exactly one of pool, split, redirect or direct_response is required
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub pool: String,
+    // Weighted division of traffic between pools, e.g. stable and canary
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub split: Vec<SplitConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub split_override: Option<SplitOverrideConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect: Option<RedirectConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direct_response: Option<DirectResponseConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<MirrorConfig>,
+    #[serde(default)]
+    pub rewrite: PathRewriteConfig,
+    #[serde(default)]
+    pub request_headers: HeaderRewriteConfig,
+    #[serde(default)]
+    pub response_headers: HeaderRewriteConfig,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<FilterConfig>,
+}
+
+// Partial changes to a pool accepted by the admin API; unset fields are
+// left as they are
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PoolUpdate {
+    #[serde(default)]
+    pub strategy: Option<Strategy>,
+    #[serde(default)]
+    pub session_timeout_seconds: Option<u64>,
+    #[serde(default)]
+    pub overprovisioning_factor: Option<f64>,
+}
+
+// Partial changes to a backend accepted by the admin API
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackendUpdate {
+    #[serde(default)]
+    pub weight: Option<u32>,
+    #[serde(default)]
+    pub priority: Option<u32>,
+    #[serde(default)]
+    pub zone: Option<String>,
+}
+
+// How the main listener treats accepted connections
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum ListenerMode {
+    #[default]
+    #[serde(rename = "http")]
+    Http,
+    // Raw layer-4 proxying: bytes are copied to the selected backend as-is
+    #[serde(rename = "tcp")]
+    Tcp,
+    // Datagram proxying with per-source-address flow affinity
+    #[serde(rename = "udp")]
+    Udp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TcpProxyConfig {
+    pub idle_timeout_seconds: u64,
+    pub connect_timeout_seconds: u64,
+}
+
+impl Default for TcpProxyConfig {
+    fn default() -> Self {
+        TcpProxyConfig {
+            idle_timeout_seconds: 300,
+            connect_timeout_seconds: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UdpProxyConfig {
+    pub flow_idle_timeout_seconds: u64,
+    // Datagrams from new client addresses are dropped while this many
+    // flows are open
+    pub max_flows: usize,
+}
+
+impl Default for UdpProxyConfig {
+    fn default() -> Self {
+        UdpProxyConfig {
+            flow_idle_timeout_seconds: 30,
+            max_flows: 10000,
+        }
+    }
+}
+
+// The config is always reloaded on SIGHUP; with `watch` set, the file is
+// also polled and reloaded when it changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReloadConfig {
+    pub watch: bool,
+    pub poll_interval_seconds: u64,
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        ReloadConfig {
+            watch: false,
+            poll_interval_seconds: 2,
+        }
+    }
+}
+
+// PEM certificate chain and private key for the HTTPS listener
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub listen_address: String,
+    pub cert_path: String,
+    pub key_path: String,
+    // UDP address for the optional HTTP/3 (QUIC) listener, using the same certificate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http3_listen_address: Option<String>,
+}
+
+// A bearer token accepted by the admin listener; `name` identifies the
+// caller in the audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminTokenConfig {
+    pub name: String,
+    pub token: String,
+}
+
+// TLS for the admin listener. With `client_ca_path`, clients must present
+// a certificate signed by that CA (mTLS) and are known by its common name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminTlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_ca_path: Option<String>,
+}
+
+// Serves the admin endpoints on their own address instead of the main
+// listener. Every request needs a bearer token or a client certificate.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminConfig {
+    // host:port, or unix:/path/to/socket
+    pub listen_address: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tokens: Vec<AdminTokenConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<AdminTlsConfig>,
+    // JSON-lines file recording every change made through the admin interface
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit_log: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoadBalancerConfig {
+    pub listen_address: String,
+    #[serde(default)]
+    pub mode: ListenerMode,
+    // Zone this balancer runs in; backends in the same zone are preferred
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    pub strategy: Strategy,
+    pub backends: Vec<BackendConfig>,
+    #[serde(default = "default_overprovisioning_factor")]
+    pub overprovisioning_factor: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slow_start: Option<SlowStartConfig>,
+    pub health_check: HealthCheckConfig,
+    pub session: SessionConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin: Option<AdminConfig>,
+    #[serde(default)]
+    pub tcp: TcpProxyConfig,
+    #[serde(default)]
+    pub udp: UdpProxyConfig,
+    #[serde(default)]
+    pub reload: ReloadConfig,
+    // Additional pools; the top-level backends form the "default" pool
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub pools: HashMap<String, PoolConfig>,
+    // Empty means every request goes to the default pool
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<RouteConfig>,
+}
+
+impl Default for LoadBalancerConfig {
+    fn default() -> Self {
+        LoadBalancerConfig {
+            listen_address: "127.0.0.1:8080".to_string(),
+            mode: ListenerMode::Http,
+            zone: None,
+            strategy: Strategy::WeightedRoundRobin,
+            backends: vec![
+                BackendConfig {
+                    url: "http://localhost:9001".to_string(),
+                    weight: Some(5),
+                    protocol: BackendProtocol::Http1,
+                    priority: 0,
+                    zone: None,
+                },
+                BackendConfig {
+                    url: "http://localhost:9002".to_string(),
+                    weight: Some(3),
+                    protocol: BackendProtocol::Http1,
+                    priority: 0,
+                    zone: None,
+                },
+                BackendConfig {
+                    url: "http://localhost:9003".to_string(),
+                    weight: Some(2),
+                    protocol: BackendProtocol::Http1,
+                    priority: 0,
+                    zone: None,
+                },
+            ],
+            overprovisioning_factor: default_overprovisioning_factor(),
+            slow_start: None,
+            health_check: HealthCheckConfig {
+                check_type: HealthCheckType::Http,
+                path: "/health".to_string(),
+                interval_seconds: 10,
+                timeout_seconds: 5,
+                max_failures: 3,
+                grpc_service: String::new(),
+            },
+            session: SessionConfig {
+                timeout_seconds: 300,
+                cookie_name: "lb_session".to_string(),
+            },
+            tls: None,
+            admin: None,
+            tcp: TcpProxyConfig::default(),
+            udp: UdpProxyConfig::default(),
+            reload: ReloadConfig::default(),
+            pools: HashMap::new(),
+            routes: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => ConfigFormat::Yaml,
+            Some("toml") => ConfigFormat::Toml,
+            _ => ConfigFormat::Json,
+        }
+    }
+}
+
+impl LoadBalancerConfig {
+    // The format follows the extension: .yaml/.yml, .toml, otherwise JSON.
+    // Parse errors carry the line and column of the problem.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = fs::read_to_string(&path)?;
+        let config = match ConfigFormat::from_path(path.as_ref()) {
+            ConfigFormat::Json => serde_json::from_str(&contents)?,
+            ConfigFormat::Yaml => serde_yaml::from_str(&contents)?,
+            ConfigFormat::Toml => toml::from_str(&contents)?,
+        };
+        Ok(config)
+    }
+
+    // Writes the default configuration if no file exists at `path`;
+    // returns whether it did
+    pub fn generate_default<P: AsRef<Path>>(path: P) -> Result<bool, Box<dyn std::error::Error>> {
+        if path.as_ref().exists() {
+            return Ok(false);
+        }
+
+        let format = ConfigFormat::from_path(path.as_ref());
+        fs::write(path, LoadBalancerConfig::default().to_string(format)?)?;
+        Ok(true)
+    }
+
+    pub fn to_string(&self, format: ConfigFormat) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(match format {
+            ConfigFormat::Json => serde_json::to_string_pretty(self)?,
+            ConfigFormat::Yaml => serde_yaml::to_string(self)?,
+            ConfigFormat::Toml => toml::to_string_pretty(self)?,
+        })
+    }
+}
+
+// Problems found by `LoadBalancerConfig::validate`, each prefixed with the
+// path of the offending field
+pub struct ConfigErrors(Vec<String>);
+
+impl ConfigErrors {
+    fn add(&mut self, field: &str, problem: impl std::fmt::Display) {
+        self.0.push(format!("{}: {}", field, problem));
+    }
+}
+
+impl std::fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} problem(s) found", self.0.len())?;
+        for error in &self.0 {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl LoadBalancerConfig {
+    // Checks the whole config and reports every problem at once, rather
+    // than stopping at the first one
+    pub fn validate(&self) -> Result<(), ConfigErrors> {
+        let mut errors = ConfigErrors(Vec::new());
+
+        check_socket_addr(&mut errors, "listen_address", &self.listen_address);
+        if let Some(tls) = &self.tls {
+            check_socket_addr(&mut errors, "tls.listen_address", &tls.listen_address);
+            if let Some(h3_addr) = &tls.http3_listen_address {
+                check_socket_addr(&mut errors, "tls.http3_listen_address", h3_addr);
+            }
+        }
+        if let Some(admin) = &self.admin {
+            match admin.listen_address.strip_prefix("unix:") {
+                Some("") => errors.add("admin.listen_address", "the socket path is empty"),
+                Some(_) => {}
+                None => {
+                    check_socket_addr(&mut errors, "admin.listen_address", &admin.listen_address)
+                }
+            }
+
+            // Tokens sent in the clear could be read off the network
+            let exposed = admin
+                .listen_address
+                .parse::<SocketAddr>()
+                .is_ok_and(|addr| !addr.ip().is_loopback());
+            if exposed && admin.tls.is_none() && !admin.tokens.is_empty() {
+                errors.add(
+                    "admin.tls",
+                    "bearer tokens over plain TCP are only accepted on a loopback address or \
+                     a unix socket; set tls or listen on one of those",
+                );
+            }
+        }
+
+        if self.mode == ListenerMode::Udp && self.udp.max_flows == 0 {
+            errors.add("udp.max_flows", "must be at least 1");
+        }
+
+        self.validate_pool(
+            &mut errors,
+            "",
+            &self.strategy,
+            &self.backends,
+            self.overprovisioning_factor,
+            self.slow_start.as_ref(),
+        );
+        validate_health_check(&mut errors, "health_check", &self.health_check, self.mode);
+
+        let mut pool_names: Vec<&String> = self.pools.keys().collect();
+        pool_names.sort();
+        for name in pool_names {
+            let pool = &self.pools[name];
+            let prefix = format!("pools.{}.", name);
+            if name == DEFAULT_POOL {
+                errors.add(
+                    &format!("pools.{}", name),
+                    format!("the name '{}' is reserved", DEFAULT_POOL),
+                );
+            }
+            self.validate_pool(
+                &mut errors,
+                &prefix,
+                &pool.strategy,
+                &pool.backends,
+                pool.overprovisioning_factor,
+                pool.slow_start.as_ref(),
+            );
+            if let Some(health_check) = &pool.health_check {
+                validate_health_check(
+                    &mut errors,
+                    &format!("{}health_check", prefix),
+                    health_check,
+                    self.mode,
+                );
+            }
+        }
+
+        let pool_exists = |name: &str| name == DEFAULT_POOL || self.pools.contains_key(name);
+        let mut route_names = HashSet::new();
+        for (i, route) in self.routes.iter().enumerate() {
+            let field = format!("routes[{}]", i);
+            if !route.name.is_empty() && !route_names.insert(&route.name) {
+                errors.add(
+                    &format!("{}.name", field),
+                    format!("'{}' is used by another route", route.name),
+                );
+            }
+            if !route.pool.is_empty() && !pool_exists(&route.pool) {
+                errors.add(
+                    &format!("{}.pool", field),
+                    format!("unknown pool '{}'", route.pool),
+                );
+            }
+            for (j, target) in route.split.iter().enumerate() {
+                if !pool_exists(&target.pool) {
+                    errors.add(
+                        &format!("{}.split[{}].pool", field, j),
+                        format!("unknown pool '{}'", target.pool),
+                    );
+                }
+            }
+            if let Some(mirror) = &route.mirror
+                && !pool_exists(&mirror.pool)
+            {
+                errors.add(
+                    &format!("{}.mirror.pool", field),
+                    format!("unknown pool '{}'", mirror.pool),
+                );
+            }
+            validate_route(&mut errors, &field, route);
+        }
+
+        if errors.0.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn validate_pool(
+        &self,
+        errors: &mut ConfigErrors,
+        prefix: &str,
+        strategy: &Strategy,
+        backends: &[BackendConfig],
+        overprovisioning_factor: f64,
+        slow_start: Option<&SlowStartConfig>,
+    ) {
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        for (i, backend) in backends.iter().enumerate() {
+            let field = format!("{}backends[{}].url", prefix, i);
+            if let Some(first) = seen.insert(&backend.url, i) {
+                errors.add(
+                    &field,
+                    format!(
+                        "'{}' is already listed as {}backends[{}]",
+                        backend.url, prefix, first
+                    ),
+                );
+            }
+            match BackendUrl::parse(&backend.url) {
+                Ok(url) => {
+                    if let Err(e) = url.validate_for(self.mode) {
+                        errors.add(&field, e);
+                    }
+                    if let Err(e) = url.validate_protocol(backend.protocol) {
+                        errors.add(&format!("{}backends[{}].protocol", prefix, i), e);
+                    }
+                }
+                Err(e) => errors.add(&field, e),
+            }
+        }
+
+        // Round robin ignores weights; the other strategies pick by them
+        let total_weight: u64 = backends
+            .iter()
+            .map(|b| u64::from(b.weight.unwrap_or(1)))
+            .sum();
+        if *strategy != Strategy::RoundRobin && !backends.is_empty() && total_weight == 0 {
+            errors.add(
+                &format!("{}backends", prefix),
+                "the total weight is 0, so no backend would ever be picked",
+            );
+        }
+
+        if !overprovisioning_factor.is_finite() || overprovisioning_factor < 1.0 {
+            errors.add(
+                &format!("{}overprovisioning_factor", prefix),
+                format!("must be at least 1.0, got {}", overprovisioning_factor),
+            );
+        }
+
+        if let Some(slow_start) = slow_start
+            && !(0.0..=100.0).contains(&slow_start.min_weight_percent)
+        {
+            errors.add(
+                &format!("{}slow_start.min_weight_percent", prefix),
+                format!(
+                    "must be between 0 and 100, got {}",
+                    slow_start.min_weight_percent
+                ),
+            );
+        }
+    }
+}
+
+// Everything Route::compile would reject, so a bad route is reported
+// with the rest of the config instead of failing at startup
+fn validate_route(errors: &mut ConfigErrors, field: &str, route: &RouteConfig) {
+    let mut add = |name: &str, result: Result<(), String>| {
+        if let Err(e) = result {
+            errors.add(&format!("{}.{}", field, name), e);
+        }
+    };
+
+    let targets = [
+        !route.pool.is_empty(),
+        !route.split.is_empty(),
+        route.redirect.is_some(),
+        route.direct_response.is_some(),
+    ];
+    if targets.iter().filter(|set| **set).count() != 1 {
+        add(
+            "pool",
+            Err("exactly one of pool, split, redirect or direct_response is required".to_string()),
+        );
+    }
+
+    if let Some(pattern) = &route.matches.path_regex {
+        add(
+            "match.path_regex",
+            Regex::new(pattern).map(|_| ()).map_err(|e| e.to_string()),
+        );
+    }
+    if let Some(redirect) = &route.redirect {
+        add(
+            "redirect.status",
+            router::redirect_status(redirect.status).map(|_| ()),
+        );
+    }
+    if let Some(direct) = &route.direct_response {
+        add(
+            "direct_response.status",
+            router::direct_status(direct.status).map(|_| ()),
+        );
+        add(
+            "direct_response.headers",
+            router::direct_headers(&direct.headers).map(|_| ()),
+        );
+    }
+    if !route.split.is_empty() {
+        add(
+            "split",
+            Split::compile(&route.split, route.split_override.as_ref()).map(|_| ()),
+        );
+    }
+    if let Some(mirror) = &route.mirror {
+        add("mirror", Mirror::compile(mirror).map(|_| ()));
+    }
+    add("rewrite", PathRewrite::compile(&route.rewrite).map(|_| ()));
+    add(
+        "request_headers",
+        HeaderRewrite::compile(&route.request_headers).map(|_| ()),
+    );
+    add(
+        "response_headers",
+        HeaderRewrite::compile(&route.response_headers).map(|_| ()),
+    );
+    for (i, filter) in route.filters.iter().enumerate() {
+        add(&format!("filters[{}]", i), filter.check());
+    }
+}
+
+fn check_socket_addr(errors: &mut ConfigErrors, field: &str, value: &str) {
+    if value.parse::<SocketAddr>().is_err() {
+        errors.add(
+            field,
+            format!("'{}' is not a valid address; expected ip:port", value),
+        );
+    }
+}
+
+fn validate_health_check(
+    errors: &mut ConfigErrors,
+    field: &str,
+    config: &HealthCheckConfig,
+    mode: ListenerMode,
+) {
+    // HTTP and gRPC probes cannot reach backends that are not HTTP servers
+    let probes_http = matches!(
+        config.check_type,
+        HealthCheckType::Http | HealthCheckType::Grpc
+    );
+    if mode != ListenerMode::Http && probes_http {
+        errors.add(
+            &format!("{}.type", field),
+            "must be 'tcp' or 'none' when mode is 'tcp' or 'udp'",
+        );
+    }
+
+    if config.interval_seconds == 0 {
+        errors.add(&format!("{}.interval_seconds", field), "must be at least 1");
+    } else if config.timeout_seconds >= config.interval_seconds {
+        errors.add(
+            &format!("{}.timeout_seconds", field),
+            format!(
+                "{} must be less than interval_seconds ({})",
+                config.timeout_seconds, config.interval_seconds
+            ),
+        );
+    }
+
+    if config.check_type == HealthCheckType::Http && !config.path.starts_with('/') {
+        errors.add(
+            &format!("{}.path", field),
+            format!("'{}' must start with '/'", config.path),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes `contents` to a scratch file named `name`, so the extension
+    // picks the format, and loads it
+    fn load(name: &str, contents: &str) -> Result<LoadBalancerConfig, String> {
+        let dir = std::env::temp_dir().join(format!("lb-config-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        LoadBalancerConfig::from_file(&path).map_err(|e| e.to_string())
+    }
+
+    const YAML: &str = "\
+listen_address: 127.0.0.1:8080
+strategy: weighted
+backends:
+  - url: http://localhost:9001
+    weight: 5   # the larger instance
+health_check:
+  path: /health
+  interval_seconds: 10
+  timeout_seconds: 5
+  max_failures: 3
+session:
+  timeout_seconds: 300
+  cookie_name: lb_session
+";
+
+    const TOML: &str = r#"listen_address = "127.0.0.1:8080"
+strategy = "weighted"
+
+[[backends]]
+url = "http://localhost:9001"
+weight = 5 # the larger instance
+
+[health_check]
+path = "/health"
+interval_seconds = 10
+timeout_seconds = 5
+max_failures = 3
+
+[session]
+timeout_seconds = 300
+cookie_name = "lb_session"
+"#;
+
+    #[test]
+    fn loads_yaml() {
+        let config = load("config.yaml", YAML).unwrap();
+        assert_eq!(config.strategy, Strategy::WeightedRoundRobin);
+        assert_eq!(config.backends[0].url, "http://localhost:9001");
+        assert_eq!(config.backends[0].weight, Some(5));
+        assert_eq!(config.health_check.interval_seconds, 10);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn loads_toml() {
+        let config = load("config.toml", TOML).unwrap();
+        assert_eq!(config.strategy, Strategy::WeightedRoundRobin);
+        assert_eq!(config.backends[0].url, "http://localhost:9001");
+        assert_eq!(config.backends[0].weight, Some(5));
+        assert_eq!(config.session.timeout_seconds, 300);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn unknown_fields_are_located() {
+        let yaml = YAML.replace("  interval_seconds", "  intervall_seconds");
+        let error = load("typo.yaml", &yaml).unwrap_err();
+        assert!(
+            error.contains("unknown field `intervall_seconds`"),
+            "{}",
+            error
+        );
+        assert!(error.contains("line 8 column 3"), "{}", error);
+
+        let toml = TOML.replace("interval_seconds", "intervall_seconds");
+        let error = load("typo.toml", &toml).unwrap_err();
+        assert!(
+            error.contains("unknown field `intervall_seconds`"),
+            "{}",
+            error
+        );
+        assert!(error.contains("line 10, column 1"), "{}", error);
+
+        let json = r#"{
+  "listen_address": "127.0.0.1:8080",
+  "strategy": "weighted",
+  "backends": [{ "url": "http://localhost:9001", "wieght": 5 }]
+}"#;
+        let error = load("typo.json", json).unwrap_err();
+        assert!(error.contains("unknown field `wieght`"), "{}", error);
+        assert!(error.contains("line 4 column"), "{}", error);
+    }
+
+    // Filters are tagged by `type`, which makes the parser read each one
+    // whole before checking its fields; a typo is reported at the start of
+    // the route's filter list
+    #[test]
+    fn unknown_filter_fields_are_located() {
+        let yaml = format!(
+            "{}routes:
+  - pool: default
+    filters:
+      - type: access_log
+      - type: rate_limit
+        requests_per_second: 5
+        brust: 10
+",
+            YAML
+        );
+        let error = load("filter.yaml", &yaml).unwrap_err();
+        assert!(error.contains("unknown field `brust`"), "{}", error);
+        assert!(error.contains("line 17 column 7"), "{}", error);
+
+        let toml = format!(
+            r#"{}
+[[routes]]
+pool = "default"
+
+[[routes.filters]]
+type = "access_log"
+
+[[routes.filters]]
+type = "rate_limit"
+requests_per_second = 5.0
+brust = 10
+"#,
+            TOML
+        );
+        let error = load("filter.toml", &toml).unwrap_err();
+        assert!(error.contains("unknown field `brust`"), "{}", error);
+        assert!(error.contains("line 21, column 1"), "{}", error);
+
+        let yaml = yaml.replace("type: rate_limit", "type: rate_limiter");
+        let error = load("filter-type.yaml", &yaml).unwrap_err();
+        assert!(
+            error.contains("unknown variant `rate_limiter`"),
+            "{}",
+            error
+        );
+    }
+
+    fn errors(config: &LoadBalancerConfig) -> Vec<String> {
+        config.validate().err().map(|e| e.0).unwrap_or_default()
+    }
+
+    fn has_error(config: &LoadBalancerConfig, field: &str) -> bool {
+        errors(config)
+            .iter()
+            .any(|e| e.starts_with(&format!("{}: ", field)))
+    }
+
+    fn with_routes(routes: &[&str]) -> LoadBalancerConfig {
+        LoadBalancerConfig {
+            routes: routes
+                .iter()
+                .map(|json| serde_json::from_str(json).unwrap())
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        assert_eq!(errors(&LoadBalancerConfig::default()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let mut config = LoadBalancerConfig {
+            listen_address: "nowhere".to_string(),
+            ..Default::default()
+        };
+        config.health_check.timeout_seconds = 10;
+        config.backends[1].url = config.backends[0].url.clone();
+
+        assert!(has_error(&config, "listen_address"));
+        assert!(has_error(&config, "health_check.timeout_seconds"));
+        assert!(has_error(&config, "backends[1].url"));
+        assert_eq!(errors(&config).len(), 3);
+    }
+
+    #[test]
+    fn checks_health_check_type_for_mode() {
+        let mut config = LoadBalancerConfig {
+            mode: ListenerMode::Tcp,
+            ..Default::default()
+        };
+        for backend in &mut config.backends {
+            backend.url = backend.url.replace("http://", "tcp://");
+        }
+        assert!(has_error(&config, "health_check.type"));
+
+        config.health_check.check_type = HealthCheckType::Tcp;
+        assert_eq!(errors(&config), Vec::<String>::new());
+    }
+
+    #[test]
+    fn rejects_plaintext_admin_tokens_off_loopback() {
+        let mut config = LoadBalancerConfig {
+            admin: Some(AdminConfig {
+                listen_address: "0.0.0.0:9090".to_string(),
+                tokens: vec![AdminTokenConfig {
+                    name: "ops".to_string(),
+                    token: "secret".to_string(),
+                }],
+                tls: None,
+                audit_log: None,
+            }),
+            ..Default::default()
+        };
+        assert!(has_error(&config, "admin.tls"));
+
+        config.admin.as_mut().unwrap().listen_address = "127.0.0.1:9090".to_string();
+        assert!(!has_error(&config, "admin.tls"));
+    }
+
+    #[test]
+    fn checks_route_targets() {
+        let config = with_routes(&[
+            r#"{ "pool": "missing" }"#,
+            r#"{ "match": { "path_prefix": "/" } }"#,
+            r#"{ "pool": "default", "redirect": { "location": "/" } }"#,
+        ]);
+
+        assert!(has_error(&config, "routes[0].pool"));
+        assert!(has_error(&config, "routes[1].pool"));
+        assert!(has_error(&config, "routes[2].pool"));
+    }
+
+    #[test]
+    fn default_pool_name_is_reserved() {
+        let pool: PoolConfig = serde_json::from_str(
+            r#"{ "strategy": "roundrobin", "backends": [{ "url": "http://localhost:9101" }] }"#,
+        )
+        .unwrap();
+        let config = LoadBalancerConfig {
+            pools: HashMap::from([(DEFAULT_POOL.to_string(), pool)]),
+            routes: with_routes(&[r#"{ "pool": "default" }"#]).routes,
+            ..Default::default()
+        };
+
+        assert!(has_error(&config, "pools.default"));
+        assert!(!has_error(&config, "routes[0].pool"));
+    }
+
+    #[test]
+    fn checks_route_settings() {
+        let config = with_routes(&[
+            r#"{ "match": { "path_regex": "(" }, "pool": "default" }"#,
+            r#"{ "redirect": { "location": "/", "status": 300 } }"#,
+            r#"{ "direct_response": { "status": 42, "headers": { "a b": "c" } } }"#,
+            r#"{ "split": [{ "pool": "default", "weight": 1 }],
+              "split_override": { "header": "a b" } }"#,
+            r#"{ "pool": "default", "rewrite": { "regex": { "pattern": "(", "replacement": "" } },
+              "request_headers": { "remove": ["a b"] },
+              "filters": [{ "type": "rate_limit", "requests_per_second": 0 }] }"#,
+        ]);
+
+        for field in [
+            "routes[0].match.path_regex",
+            "routes[1].redirect.status",
+            "routes[2].direct_response.status",
+            "routes[2].direct_response.headers",
+            "routes[3].split",
+            "routes[4].rewrite",
+            "routes[4].request_headers",
+            "routes[4].filters[0]",
+        ] {
+            assert!(
+                has_error(&config, field),
+                "{}: {:?}",
+                field,
+                errors(&config)
+            );
+        }
+        assert_eq!(errors(&config).len(), 8);
+    }
+
+    #[test]
+    fn rejects_duplicate_route_names() {
+        let config = with_routes(&[
+            r#"{ "name": "api", "pool": "default" }"#,
+            r#"{ "name": "api", "pool": "default" }"#,
+        ]);
+        assert!(has_error(&config, "routes[1].name"));
+    }
+}
@@ -0,0 +1,565 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use hyper::header::{AUTHORIZATION, HeaderValue, RETRY_AFTER, WWW_AUTHENTICATE};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::{info, warn};
+use subtle::ConstantTimeEq;
+
+use crate::config::{FilterConfig, HeaderRewriteConfig, RouteTokenConfig};
+use crate::rewrite::{self, HeaderRewrite, Variables};
+
+// How often rate limit buckets that have refilled are dropped
+const BUCKET_SWEEP_SECONDS: u64 = 60;
+
+// A step of a route's request processing, e.g. auth or logging. Filters
+// are shared by concurrent requests; anything kept across requests needs
+// interior mutability. Every phase defaults to doing nothing.
+pub trait Filter: Send + Sync {
+    // Runs in chain order once the route is matched, before anything else
+    // happens to the request. Returning a response ends the request there.
+    fn on_request(
+        &self,
+        _req: &mut Request<Body>,
+        _ctx: &mut FilterContext,
+    ) -> Option<Response<Body>> {
+        None
+    }
+
+    // Runs in chain order once a backend is picked, just before the request
+    // is sent to it; `ctx.backend` is set
+    fn on_upstream_request(&self, _req: &mut Request<Body>, _ctx: &FilterContext) {}
+
+    // The request failed after passing the request phase: no backend could
+    // take it, or the backend could not be reached. `response` is the
+    // error response about to be sent. Runs in reverse chain order.
+    fn on_error(&self, _error: &ProxyError, _response: &mut Response<Body>, _ctx: &FilterContext) {}
+
+    // Runs in reverse chain order on every response of the route, including
+    // those from other filters, redirects and errors. Streamed bodies (e.g.
+    // gRPC) are still in flight at this point.
+    fn on_response(&self, _response: &mut Response<Body>, _ctx: &FilterContext) {}
+}
+
+#[derive(Debug)]
+pub enum ProxyError {
+    NoBackend { pool: String },
+    Upstream { backend: String, message: String },
+}
+
+impl std::fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProxyError::NoBackend { pool } => {
+                write!(f, "no healthy backends available in pool '{}'", pool)
+            }
+            ProxyError::Upstream { backend, message } => write!(f, "{}: {}", backend, message),
+        }
+    }
+}
+
+// What filters know about the request they are handling
+pub struct FilterContext {
+    pub method: Method,
+    // Path and query as received, before any rewriting
+    pub uri: String,
+    // Address of the connecting peer; unlike X-Forwarded-For it cannot be
+    // set by the client
+    pub client_ip: String,
+    pub request_id: String,
+    pub route: String,
+    // Set once a backend is picked
+    pub backend: Option<String>,
+    // Who an auth filter let the request through as
+    pub principal: Option<String>,
+    pub started: Instant,
+}
+
+impl FilterContext {
+    pub fn new(req: &Request<Body>, remote_addr: SocketAddr, route: &str) -> Self {
+        FilterContext {
+            method: req.method().clone(),
+            uri: req
+                .uri()
+                .path_and_query()
+                .map_or("/", |p| p.as_str())
+                .to_string(),
+            client_ip: remote_addr.ip().to_string(),
+            request_id: rewrite::request_id(req),
+            route: route.to_string(),
+            backend: None,
+            principal: None,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn variables(&self) -> Variables<'_> {
+        Variables {
+            client_ip: &self.client_ip,
+            request_id: &self.request_id,
+            backend: self.backend.as_deref().unwrap_or(""),
+        }
+    }
+}
+
+// A route's filters, in the order they were configured
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn Filter>>,
+}
+
+impl FilterChain {
+    // The route's request_headers and response_headers come first, so
+    // filters listed in the config see the rewritten headers
+    pub fn compile(
+        configs: &[FilterConfig],
+        request_headers: &HeaderRewriteConfig,
+        response_headers: &HeaderRewriteConfig,
+    ) -> Result<Self, String> {
+        let mut chain = FilterChain::default();
+        if !request_headers.is_empty() || !response_headers.is_empty() {
+            chain.push(HeaderFilter::compile(request_headers, response_headers)?);
+        }
+
+        for (i, config) in configs.iter().enumerate() {
+            config
+                .check()
+                .map_err(|e| format!("filters[{}]: {}", i, e))?;
+            let filter: Box<dyn Filter> = match config {
+                FilterConfig::Auth { tokens } => Box::new(AuthFilter::compile(tokens)),
+                FilterConfig::RateLimit {
+                    requests_per_second,
+                    burst,
+                } => Box::new(RateLimitFilter::compile(*requests_per_second, *burst)),
+                FilterConfig::Headers { request, response } => {
+                    Box::new(HeaderFilter::compile(request, response)?)
+                }
+                FilterConfig::AccessLog {} => Box::new(AccessLogFilter),
+            };
+            chain.filters.push(filter);
+        }
+
+        Ok(chain)
+    }
+
+    pub fn push(&mut self, filter: impl Filter + 'static) {
+        self.filters.push(Box::new(filter));
+    }
+
+    // The response of the first filter that ends the request, if any
+    pub fn on_request(
+        &self,
+        req: &mut Request<Body>,
+        ctx: &mut FilterContext,
+    ) -> Option<Response<Body>> {
+        self.filters
+            .iter()
+            .find_map(|filter| filter.on_request(req, ctx))
+    }
+
+    pub fn on_upstream_request(&self, req: &mut Request<Body>, ctx: &FilterContext) {
+        for filter in &self.filters {
+            filter.on_upstream_request(req, ctx);
+        }
+    }
+
+    pub fn on_error(&self, error: &ProxyError, response: &mut Response<Body>, ctx: &FilterContext) {
+        for filter in self.filters.iter().rev() {
+            filter.on_error(error, response, ctx);
+        }
+    }
+
+    pub fn on_response(&self, response: &mut Response<Body>, ctx: &FilterContext) {
+        for filter in self.filters.iter().rev() {
+            filter.on_response(response, ctx);
+        }
+    }
+}
+
+// Requires `Authorization: Bearer <token>` with one of the configured
+// tokens. The header is removed before the request is forwarded.
+struct AuthFilter {
+    tokens: Vec<RouteTokenConfig>,
+}
+
+impl AuthFilter {
+    fn compile(tokens: &[RouteTokenConfig]) -> Self {
+        AuthFilter {
+            tokens: tokens.to_vec(),
+        }
+    }
+
+    fn authenticate(&self, req: &Request<Body>) -> Option<&str> {
+        let value = req.headers().get(AUTHORIZATION)?;
+        let token = value.to_str().ok()?.strip_prefix("Bearer ")?.trim();
+        self.tokens
+            .iter()
+            .find(|t| bool::from(t.token.as_bytes().ct_eq(token.as_bytes())))
+            .map(|t| t.name.as_str())
+    }
+}
+
+impl Filter for AuthFilter {
+    fn on_request(
+        &self,
+        req: &mut Request<Body>,
+        ctx: &mut FilterContext,
+    ) -> Option<Response<Body>> {
+        let Some(name) = self.authenticate(req) else {
+            warn!(
+                "Rejected unauthenticated request from {} on route '{}'",
+                ctx.client_ip, ctx.route
+            );
+            return Some(
+                Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header(WWW_AUTHENTICATE, "Bearer")
+                    .body(Body::from("Unauthorized"))
+                    .unwrap(),
+            );
+        };
+
+        ctx.principal = Some(name.to_string());
+        req.headers_mut().remove(AUTHORIZATION);
+        None
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+// Token bucket per client address: `burst` requests at once, refilled at
+// `requests_per_second`
+struct RateLimitFilter {
+    rate: f64,
+    burst: f64,
+    buckets: DashMap<String, Bucket>,
+    started: Instant,
+    last_sweep: AtomicU64,
+}
+
+impl RateLimitFilter {
+    fn compile(requests_per_second: f64, burst: Option<u32>) -> Self {
+        let burst = burst.map_or(requests_per_second.ceil().max(1.0), f64::from);
+        RateLimitFilter {
+            rate: requests_per_second,
+            burst,
+            buckets: DashMap::new(),
+            started: Instant::now(),
+            last_sweep: AtomicU64::new(0),
+        }
+    }
+
+    // Takes a token for `client`; on failure returns how long until the
+    // next one is available
+    fn acquire(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        let mut bucket = self
+            .buckets
+            .entry(client.to_string())
+            .or_insert_with(|| Bucket {
+                tokens: self.burst,
+                refilled: now,
+            });
+
+        let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.refilled = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    // Drops buckets that are full again, since a new bucket starts out the
+    // same; at most once per BUCKET_SWEEP_SECONDS
+    fn sweep_full(&self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.started).as_secs();
+        let last = self.last_sweep.load(Ordering::Relaxed);
+        if elapsed < last + BUCKET_SWEEP_SECONDS
+            || self
+                .last_sweep
+                .compare_exchange(last, elapsed, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+        {
+            return;
+        }
+
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+            bucket.tokens + elapsed * self.rate < self.burst
+        });
+    }
+}
+
+impl Filter for RateLimitFilter {
+    fn on_request(
+        &self,
+        _req: &mut Request<Body>,
+        ctx: &mut FilterContext,
+    ) -> Option<Response<Body>> {
+        let now = Instant::now();
+        self.sweep_full(now);
+
+        let Err(wait) = self.acquire(&ctx.client_ip, now) else {
+            return None;
+        };
+
+        warn!("Rate limited {} on route '{}'", ctx.client_ip, ctx.route);
+        let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+        Some(
+            Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header(RETRY_AFTER, HeaderValue::from(retry_after))
+                .body(Body::from("Too Many Requests"))
+                .unwrap(),
+        )
+    }
+}
+
+// Request headers are rewritten once the backend is known, so values may
+// use $backend
+struct HeaderFilter {
+    request: HeaderRewrite,
+    response: HeaderRewrite,
+}
+
+impl HeaderFilter {
+    fn compile(
+        request: &HeaderRewriteConfig,
+        response: &HeaderRewriteConfig,
+    ) -> Result<Self, String> {
+        Ok(HeaderFilter {
+            request: HeaderRewrite::compile(request)?,
+            response: HeaderRewrite::compile(response)?,
+        })
+    }
+}
+
+impl Filter for HeaderFilter {
+    fn on_upstream_request(&self, req: &mut Request<Body>, ctx: &FilterContext) {
+        self.request.apply(req.headers_mut(), &ctx.variables());
+    }
+
+    fn on_response(&self, response: &mut Response<Body>, ctx: &FilterContext) {
+        self.response
+            .apply(response.headers_mut(), &ctx.variables());
+    }
+}
+
+// One line per request under the "access" log target
+struct AccessLogFilter;
+
+impl Filter for AccessLogFilter {
+    fn on_response(&self, response: &mut Response<Body>, ctx: &FilterContext) {
+        info!(
+            target: "access",
+            "{} {} {} {} {} {:.1}ms backend={} user={} request_id={}",
+            ctx.client_ip,
+            ctx.method,
+            ctx.uri,
+            response.status().as_u16(),
+            ctx.route,
+            ctx.started.elapsed().as_secs_f64() * 1000.0,
+            ctx.backend.as_deref().unwrap_or("-"),
+            ctx.principal.as_deref().unwrap_or("-"),
+            ctx.request_id,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // Records which phases ran, and in what order, into a shared log
+    struct Recorder {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Filter for Recorder {
+        fn on_request(
+            &self,
+            _req: &mut Request<Body>,
+            _ctx: &mut FilterContext,
+        ) -> Option<Response<Body>> {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} request", self.name));
+            None
+        }
+
+        fn on_response(&self, _response: &mut Response<Body>, _ctx: &FilterContext) {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} response", self.name));
+        }
+    }
+
+    fn chain(configs: serde_json::Value) -> FilterChain {
+        let configs: Vec<FilterConfig> = serde_json::from_value(configs).unwrap();
+        FilterChain::compile(&configs, &Default::default(), &Default::default()).unwrap()
+    }
+
+    fn request(authorization: Option<&str>) -> (Request<Body>, FilterContext) {
+        let mut builder = Request::builder().uri("/api");
+        if let Some(value) = authorization {
+            builder = builder.header(AUTHORIZATION, value);
+        }
+        let req = builder.body(Body::empty()).unwrap();
+        let ctx = FilterContext::new(&req, "10.0.0.1:5000".parse().unwrap(), "api");
+        (req, ctx)
+    }
+
+    // Auth between two recorders
+    fn auth_chain(log: &Arc<Mutex<Vec<String>>>) -> FilterChain {
+        let mut filters = FilterChain::default();
+        filters.push(Recorder {
+            name: "first",
+            log: log.clone(),
+        });
+        filters.filters.append(
+            &mut chain(serde_json::json!([
+                { "type": "auth", "tokens": [{ "name": "app", "token": "secret" }] }
+            ]))
+            .filters,
+        );
+        filters.push(Recorder {
+            name: "last",
+            log: log.clone(),
+        });
+        filters
+    }
+
+    fn values(headers: &hyper::HeaderMap, name: &str) -> Vec<String> {
+        headers
+            .get_all(name)
+            .iter()
+            .map(|v| v.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn auth_rejection_skips_later_filters() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let chain = auth_chain(&log);
+
+        for authorization in [None, Some("Bearer wrong"), Some("secret")] {
+            let (mut req, mut ctx) = request(authorization);
+            let response = chain.on_request(&mut req, &mut ctx).unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(response.headers()[WWW_AUTHENTICATE], "Bearer");
+            assert_eq!(ctx.principal, None);
+        }
+        assert_eq!(*log.lock().unwrap(), vec!["first request"; 3]);
+    }
+
+    #[test]
+    fn auth_passes_known_tokens_without_the_header() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let chain = auth_chain(&log);
+
+        let (mut req, mut ctx) = request(Some("Bearer secret"));
+        assert!(chain.on_request(&mut req, &mut ctx).is_none());
+        assert_eq!(ctx.principal.as_deref(), Some("app"));
+        assert!(!req.headers().contains_key(AUTHORIZATION));
+        assert_eq!(*log.lock().unwrap(), vec!["first request", "last request"]);
+    }
+
+    #[test]
+    fn responses_run_in_reverse_even_after_a_rejection() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let chain = auth_chain(&log);
+
+        let (mut req, mut ctx) = request(None);
+        let mut response = chain.on_request(&mut req, &mut ctx).unwrap();
+        chain.on_response(&mut response, &ctx);
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["first request", "last response", "first response"]
+        );
+    }
+
+    #[test]
+    fn rate_limit_refills_over_time() {
+        let limit = RateLimitFilter::compile(2.0, Some(2));
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        assert_eq!(limit.acquire("a", at(0)), Ok(()));
+        assert_eq!(limit.acquire("a", at(0)), Ok(()));
+        assert_eq!(limit.acquire("a", at(0)), Err(Duration::from_millis(500)));
+        // Buckets are per client
+        assert_eq!(limit.acquire("b", at(0)), Ok(()));
+
+        assert_eq!(limit.acquire("a", at(250)), Err(Duration::from_millis(250)));
+        assert_eq!(limit.acquire("a", at(500)), Ok(()));
+        assert_eq!(limit.acquire("a", at(500)), Err(Duration::from_millis(500)));
+
+        // An idle client gets no more than the burst
+        assert_eq!(limit.acquire("a", at(60_000)), Ok(()));
+        assert_eq!(limit.acquire("a", at(60_000)), Ok(()));
+        assert!(limit.acquire("a", at(60_000)).is_err());
+    }
+
+    #[test]
+    fn rate_limit_answers_429_with_retry_after() {
+        let chain = chain(serde_json::json!([
+            { "type": "rate_limit", "requests_per_second": 0.5 }
+        ]));
+
+        let (mut req, mut ctx) = request(None);
+        assert!(chain.on_request(&mut req, &mut ctx).is_none());
+
+        let response = chain.on_request(&mut req, &mut ctx).unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "2");
+    }
+
+    #[test]
+    fn headers_apply_in_chain_order_and_reverse_on_response() {
+        let route_headers: HeaderRewriteConfig = serde_json::from_value(serde_json::json!({
+            "set": { "x-stage": "route" },
+            "add": { "x-trace": "route" }
+        }))
+        .unwrap();
+        let configs: Vec<FilterConfig> = serde_json::from_value(serde_json::json!([{
+            "type": "headers",
+            "request": { "remove": ["x-stage"], "add": { "x-trace": "filter" } },
+            "response": { "set": { "x-stage": "filter" }, "add": { "x-trace": "filter" } }
+        }]))
+        .unwrap();
+        let route_response: HeaderRewriteConfig = serde_json::from_value(serde_json::json!({
+            "remove": ["x-stage"],
+            "add": { "x-trace": "route" }
+        }))
+        .unwrap();
+        let chain = FilterChain::compile(&configs, &route_headers, &route_response).unwrap();
+
+        // The route's headers come first on the way in...
+        let (mut req, ctx) = request(None);
+        chain.on_upstream_request(&mut req, &ctx);
+        assert!(!req.headers().contains_key("x-stage"));
+        assert_eq!(values(req.headers(), "x-trace"), vec!["route", "filter"]);
+
+        // ...and last on the way out
+        let mut response = Response::new(Body::empty());
+        chain.on_response(&mut response, &ctx);
+        assert!(!response.headers().contains_key("x-stage"));
+        assert_eq!(
+            values(response.headers(), "x-trace"),
+            vec!["filter", "route"]
+        );
+    }
+}
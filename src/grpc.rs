@@ -0,0 +1,208 @@
+use std::future::Future;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use hyper::Request;
+use hyper::body::{Body, HttpBody};
+use hyper::header::{CONTENT_TYPE, HeaderMap};
+use log::warn;
+
+pub const HEALTH_CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+
+pub const STATUS_OK: u32 = 0;
+
+// grpc.health.v1.HealthCheckResponse.ServingStatus.SERVING
+const SERVING: u64 = 1;
+
+// Statuses that point at the backend rather than the call, i.e. the ones an
+// HTTP proxy would report as 5xx: UNKNOWN, DEADLINE_EXCEEDED, INTERNAL,
+// UNAVAILABLE and DATA_LOSS.
+const FAILURE_STATUSES: [u32; 5] = [2, 4, 13, 14, 15];
+
+pub fn is_grpc_request<B>(req: &Request<B>) -> bool {
+    req.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/grpc"))
+}
+
+pub fn grpc_status(headers: &HeaderMap) -> Option<u32> {
+    headers
+        .get("grpc-status")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+pub fn is_failure_status(status: u32) -> bool {
+    FAILURE_STATUSES.contains(&status)
+}
+
+// Length-prefixed message carrying a HealthCheckRequest { service = 1 }
+pub fn encode_health_check_request(service: &str) -> Bytes {
+    let mut message = BytesMut::new();
+    if !service.is_empty() {
+        message.put_u8(0x0a);
+        put_varint(&mut message, service.len() as u64);
+        message.put_slice(service.as_bytes());
+    }
+
+    let mut frame = BytesMut::with_capacity(5 + message.len());
+    frame.put_u8(0);
+    frame.put_u32(message.len() as u32);
+    frame.put_slice(&message);
+    frame.freeze()
+}
+
+// True if the length-prefixed HealthCheckResponse reports SERVING
+pub fn is_serving(frame: &[u8]) -> bool {
+    if frame.len() < 5 || frame[0] != 0 {
+        return false;
+    }
+
+    let len = u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]) as usize;
+    frame
+        .get(5..5 + len)
+        .and_then(serving_status)
+        .is_some_and(|status| status == SERVING)
+}
+
+fn serving_status(mut message: &[u8]) -> Option<u64> {
+    while !message.is_empty() {
+        let tag = take_varint(&mut message)?;
+        match tag & 0x07 {
+            // varint; field 1 is the status
+            0 => {
+                let value = take_varint(&mut message)?;
+                if tag >> 3 == 1 {
+                    return Some(value);
+                }
+            }
+            // length-delimited, skipped
+            2 => {
+                let len = take_varint(&mut message)? as usize;
+                message = message.get(len..)?;
+            }
+            _ => return None,
+        }
+    }
+
+    // An omitted status is the proto default, UNKNOWN
+    Some(0)
+}
+
+// Streams `body` into a new body, preserving trailers, and reports the final
+// grpc-status once the call ends. `None` means the stream broke or finished
+// without a status, which gRPC treats as a failed call. If the client goes
+// away first, `on_status` is dropped without being called.
+pub fn relay_with_status<F, Fut>(mut body: Body, on_status: F) -> Body
+where
+    F: FnOnce(Option<u32>) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let (mut sender, relayed) = Body::channel();
+
+    tokio::spawn(async move {
+        while let Some(chunk) = body.data().await {
+            match chunk {
+                Ok(data) => {
+                    // The client went away; the call outcome is unknown
+                    if sender.send_data(data).await.is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    warn!("gRPC response stream failed: {}", e);
+                    sender.abort();
+                    on_status(None).await;
+                    return;
+                }
+            }
+        }
+
+        match body.trailers().await {
+            Ok(Some(trailers)) => {
+                let status = grpc_status(&trailers);
+                let _ = sender.send_trailers(trailers).await;
+                on_status(status).await;
+            }
+            Ok(None) => on_status(None).await,
+            Err(e) => {
+                warn!("Failed to read gRPC trailers: {}", e);
+                sender.abort();
+                on_status(None).await;
+            }
+        }
+    });
+
+    relayed
+}
+
+fn put_varint(buf: &mut BytesMut, mut value: u64) {
+    while value >= 0x80 {
+        buf.put_u8((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+fn take_varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf.split_first()?;
+        *buf = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_status_field() {
+        assert_eq!(serving_status(&[0x08, 0x01]), Some(SERVING));
+        assert_eq!(serving_status(&[0x08, 0x02]), Some(2));
+    }
+
+    #[test]
+    fn omitted_status_is_unknown() {
+        assert_eq!(serving_status(&[]), Some(0));
+    }
+
+    #[test]
+    fn skips_other_fields() {
+        // field 2 as a string, field 3 as a varint, then the status
+        let message = [0x12, 0x02, b'h', b'i', 0x18, 0x96, 0x01, 0x08, 0x01];
+        assert_eq!(serving_status(&message), Some(SERVING));
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        // truncated varint, overlong string, unsupported wire type
+        assert_eq!(serving_status(&[0x08, 0x80]), None);
+        assert_eq!(serving_status(&[0x12, 0x05, b'h']), None);
+        assert_eq!(serving_status(&[0x0d, 0, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn checks_frame_header() {
+        assert!(is_serving(&[0, 0, 0, 0, 2, 0x08, 0x01]));
+        assert!(!is_serving(&[0, 0, 0, 0, 2, 0x08, 0x02]));
+        // compressed flag, truncated message, short frame
+        assert!(!is_serving(&[1, 0, 0, 0, 2, 0x08, 0x01]));
+        assert!(!is_serving(&[0, 0, 0, 0, 3, 0x08, 0x01]));
+        assert!(!is_serving(&[0, 0, 0]));
+    }
+
+    #[test]
+    fn encodes_request() {
+        assert_eq!(&encode_health_check_request("")[..], &[0, 0, 0, 0, 0]);
+        assert_eq!(
+            &encode_health_check_request("svc")[..],
+            &[0, 0, 0, 0, 5, 0x0a, 3, b's', b'v', b'c']
+        );
+    }
+}
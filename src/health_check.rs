@@ -1,78 +1,152 @@
 use std::sync::Arc;
 use std::time::Duration;
 
-use hyper::body::Body;
-use hyper::client::HttpConnector;
-use hyper::{Client, Method, Request};
+use hyper::body::{Body, HttpBody};
+use hyper::header::{CONTENT_TYPE, TE};
+use hyper::{Method, Request};
 use log::{error, info, warn};
-use tokio::sync::Mutex;
-use tokio::time::sleep;
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout};
 
-use crate::config::HealthCheckConfig;
+use crate::backend_url::BackendUrl;
+use crate::client::{BackendClients, HttpsClient};
+use crate::config::{HealthCheckConfig, HealthCheckType};
+use crate::grpc;
 use crate::load_balancer::LoadBalancer;
 
-pub async fn health_check(
-    lb: Arc<Mutex<LoadBalancer>>,
-    client: Client<HttpConnector>,
-    config: HealthCheckConfig,
-) {
-    let interval = Duration::from_secs(config.interval_seconds);
-    let timeout = Duration::from_secs(config.timeout_seconds);
-
+pub async fn health_check(lb: Arc<LoadBalancer>, pool_name: String, clients: BackendClients) {
     loop {
+        // Settings are re-read every round so runtime changes take effect
+        let Some(config) = lb.pool(&pool_name).map(|pool| pool.health_check_config()) else {
+            info!("Pool '{}' is gone, stopping its health checker", pool_name);
+            return;
+        };
+
+        let interval = Duration::from_secs(config.interval_seconds);
+        let check_timeout = Duration::from_secs(config.timeout_seconds);
+
         sleep(interval).await;
 
-        let backends = {
-            let lb = lb.lock().await;
-            lb.get_all_backends()
+        let Some(pool) = lb.pool(&pool_name) else {
+            continue;
         };
 
-        for backend in backends {
-            info!("Performing health check on {}", backend);
-
-            let uri = format!("{}{}", backend, config.path);
-            let req = Request::builder()
-                .method(Method::GET)
-                .uri(uri)
-                .body(Body::empty())
-                .unwrap();
-
-            let response_future = client.request(req);
-
-            // TODO: Implement proper timeout handling
-            // For now, we just await the response without timeout
-            match response_future.await {
-                Ok(response) => {
-                    if response.status().is_success() {
-                        info!("Health check succeeded for {}", backend);
-                        let mut lb = lb.lock().await;
-                        lb.mark_healthy(&backend);
-                    } else {
-                        warn!(
-                            "Health check failed for {} with status {}",
-                            backend,
-                            response.status()
-                        );
-                        let mut lb = lb.lock().await;
-                        lb.mark_unhealthy(&backend);
-                    }
+        for backend in pool.backends() {
+            info!(
+                "Performing health check on {} (pool '{}')",
+                backend.url, pool_name
+            );
+
+            let client = clients.get(backend.protocol);
+            let check = async {
+                match config.check_type {
+                    HealthCheckType::Http => check_http(client, &backend.target, &config).await,
+                    HealthCheckType::Grpc => check_grpc(client, &backend.target, &config).await,
+                    HealthCheckType::Tcp => check_tcp(&backend.target).await,
+                    HealthCheckType::None => Ok(()),
+                }
+            };
+
+            match timeout(check_timeout, check).await {
+                Ok(Ok(())) => {
+                    info!("Health check succeeded for {}", backend.url);
+                    pool.mark_healthy(&backend);
                 }
-                Err(e) => {
-                    error!("Health check error for {}: {}", backend, e);
-                    let mut lb = lb.lock().await;
-                    lb.mark_unhealthy(&backend);
+                Ok(Err(reason)) => {
+                    warn!("Health check failed for {}: {}", backend.url, reason);
+                    pool.mark_unhealthy(&backend);
+                }
+                Err(_) => {
+                    error!(
+                        "Health check for {} timed out after {:?}",
+                        backend.url, check_timeout
+                    );
+                    pool.mark_unhealthy(&backend);
                 }
             }
         }
     }
 }
 
-pub fn start_health_checker(
-    lb: Arc<Mutex<LoadBalancer>>,
-    client: Client<HttpConnector>,
-    config: HealthCheckConfig,
-) {
+async fn check_tcp(backend: &BackendUrl) -> Result<(), String> {
+    let addr = backend.address().ok_or("no host:port in backend URL")?;
+    TcpStream::connect(addr)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+async fn check_http(
+    client: &HttpsClient,
+    backend: &BackendUrl,
+    config: &HealthCheckConfig,
+) -> Result<(), String> {
+    let uri = backend.join(&config.path)?;
+    let req = Request::builder()
+        .method(Method::GET)
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap();
+
+    let response = client.request(req).await.map_err(|e| e.to_string())?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("status {}", response.status()))
+    }
+}
+
+// Standard grpc.health.v1.Health/Check; the backend must report SERVING
+async fn check_grpc(
+    client: &HttpsClient,
+    backend: &BackendUrl,
+    config: &HealthCheckConfig,
+) -> Result<(), String> {
+    let uri = backend.join(grpc::HEALTH_CHECK_PATH)?;
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(CONTENT_TYPE, "application/grpc")
+        .header(TE, "trailers")
+        .body(Body::from(grpc::encode_health_check_request(
+            &config.grpc_service,
+        )))
+        .unwrap();
+
+    let response = client.request(req).await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("status {}", response.status()));
+    }
+
+    // Trailers-only responses carry grpc-status in the headers
+    if let Some(status) = grpc::grpc_status(response.headers())
+        && status != grpc::STATUS_OK
+    {
+        return Err(format!("grpc-status {}", status));
+    }
+
+    let mut body = response.into_body();
+    let mut message = Vec::new();
+    while let Some(chunk) = body.data().await {
+        message.extend_from_slice(&chunk.map_err(|e| e.to_string())?);
+    }
+
+    let trailers = body.trailers().await.map_err(|e| e.to_string())?;
+    if let Some(status) = trailers.as_ref().and_then(grpc::grpc_status)
+        && status != grpc::STATUS_OK
+    {
+        return Err(format!("grpc-status {}", status));
+    }
+
+    if grpc::is_serving(&message) {
+        Ok(())
+    } else {
+        Err("service is not SERVING".to_string())
+    }
+}
+
+pub fn start_health_checker(lb: Arc<LoadBalancer>, pool_name: String, clients: BackendClients) {
     tokio::spawn(async move {
-        health_check(lb, client, config).await;
+        health_check(lb, pool_name, clients).await;
     });
 }
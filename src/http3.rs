@@ -0,0 +1,276 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use bytes::{Buf, Bytes};
+use h3::server::RequestStream;
+use h3_quinn::{BidiStream, RecvStream};
+use hyper::body::{Body, HttpBody};
+use hyper::header::{ALT_SVC, HeaderMap, HeaderName, HeaderValue};
+use hyper::{Method, Request, Uri, Version};
+use log::{error, info, warn};
+use quinn::crypto::rustls::QuicServerConfig;
+use tokio_rustls::rustls::ServerConfig;
+
+use crate::client::BackendClients;
+use crate::load_balancer::LoadBalancer;
+use crate::load_balancer::service::handle_request;
+use crate::router::SharedRouter;
+
+const HOP_BY_HOP_HEADERS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+pub async fn serve_http3(
+    addr: SocketAddr,
+    server_config: Arc<ServerConfig>,
+    lb: Arc<LoadBalancer>,
+    router: Arc<SharedRouter>,
+    clients: BackendClients,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Same certificate as the HTTPS listener, but QUIC only speaks h3
+    let mut tls_config = (*server_config).clone();
+    tls_config.alpn_protocols = vec![b"h3".to_vec()];
+    let quic_config = QuicServerConfig::try_from(tls_config)?;
+    let endpoint = quinn::Endpoint::server(
+        quinn::ServerConfig::with_crypto(Arc::new(quic_config)),
+        addr,
+    )?;
+
+    info!("Starting HTTP/3 listener on {}", addr);
+
+    while let Some(incoming) = endpoint.accept().await {
+        let lb = lb.clone();
+        let router = router.clone();
+        let clients = clients.clone();
+
+        tokio::spawn(async move {
+            let conn = match incoming.await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    warn!("QUIC handshake failed: {}", e);
+                    return;
+                }
+            };
+            let remote_addr = conn.remote_address();
+
+            let mut h3_conn =
+                match h3::server::Connection::new(h3_quinn::Connection::new(conn)).await {
+                    Ok(h3_conn) => h3_conn,
+                    Err(e) => {
+                        warn!("HTTP/3 setup with {} failed: {}", remote_addr, e);
+                        return;
+                    }
+                };
+
+            loop {
+                match h3_conn.accept().await {
+                    Ok(Some(resolver)) => {
+                        let lb = lb.clone();
+                        let router = router.clone();
+                        let clients = clients.clone();
+
+                        tokio::spawn(async move {
+                            match resolver.resolve_request().await {
+                                Ok((req, stream)) => {
+                                    if let Err(e) =
+                                        serve_request(req, stream, lb, router, clients, remote_addr)
+                                            .await
+                                    {
+                                        warn!("HTTP/3 stream from {} failed: {}", remote_addr, e);
+                                    }
+                                }
+                                Err(e) => {
+                                    warn!("Invalid HTTP/3 request from {}: {}", remote_addr, e)
+                                }
+                            }
+                        });
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        if !e.is_h3_no_error() {
+                            warn!("HTTP/3 connection from {} closed: {}", remote_addr, e);
+                        }
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    error!("HTTP/3 endpoint on {} stopped accepting", addr);
+
+    Ok(())
+}
+
+// Bridges one h3 request stream onto `handle_request`, so HTTP/3 shares
+// routing and backend selection with the HTTP/1 and HTTP/2 listeners.
+async fn serve_request(
+    req: http1::Request<()>,
+    stream: RequestStream<BidiStream<Bytes>, Bytes>,
+    lb: Arc<LoadBalancer>,
+    router: Arc<SharedRouter>,
+    clients: BackendClients,
+    remote_addr: SocketAddr,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (mut send, recv) = stream.split();
+
+    let (parts, ()) = req.into_parts();
+    let mut request = Request::new(relay_request_body(recv));
+    *request.method_mut() = Method::from_bytes(parts.method.as_str().as_bytes())?;
+    *request.uri_mut() = parts.uri.to_string().parse::<Uri>()?;
+    *request.version_mut() = Version::HTTP_3;
+    *request.headers_mut() = to_hyper_headers(&parts.headers);
+
+    let Ok(response) = handle_request(request, lb, router, clients, remote_addr).await;
+
+    let (parts, mut body) = response.into_parts();
+    let mut head = http1::Response::new(());
+    *head.status_mut() = http1::StatusCode::from_u16(parts.status.as_u16())?;
+    *head.headers_mut() = to_h3_headers(&parts.headers);
+    // Connection-specific headers are not allowed in HTTP/3
+    for name in HOP_BY_HOP_HEADERS {
+        head.headers_mut().remove(name);
+    }
+    send.send_response(head).await?;
+
+    while let Some(chunk) = body.data().await {
+        send.send_data(chunk?).await?;
+    }
+    if let Some(trailers) = body.trailers().await? {
+        send.send_trailers(to_h3_headers(&trailers)).await?;
+    }
+    send.finish().await?;
+
+    Ok(())
+}
+
+fn relay_request_body(mut recv: RequestStream<RecvStream, Bytes>) -> Body {
+    let (mut sender, body) = Body::channel();
+
+    tokio::spawn(async move {
+        loop {
+            match recv.recv_data().await {
+                Ok(Some(mut chunk)) => {
+                    let data = chunk.copy_to_bytes(chunk.remaining());
+                    if sender.send_data(data).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("HTTP/3 request body failed: {}", e);
+                    sender.abort();
+                    return;
+                }
+            }
+        }
+
+        match recv.recv_trailers().await {
+            Ok(Some(trailers)) => {
+                let _ = sender.send_trailers(to_hyper_headers(&trailers)).await;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!("HTTP/3 request trailers failed: {}", e);
+                sender.abort();
+            }
+        }
+    });
+
+    body
+}
+
+// h3 is built on http 1.x while hyper 0.14 uses http 0.2, so headers are
+// copied across by their raw bytes.
+fn to_hyper_headers(headers: &http1::HeaderMap) -> HeaderMap {
+    let mut converted = HeaderMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_str().as_bytes()),
+            HeaderValue::from_bytes(value.as_bytes()),
+        ) {
+            converted.append(name, value);
+        }
+    }
+    converted
+}
+
+fn to_h3_headers(headers: &HeaderMap) -> http1::HeaderMap {
+    let mut converted = http1::HeaderMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (
+            http1::HeaderName::from_bytes(name.as_str().as_bytes()),
+            http1::HeaderValue::from_bytes(value.as_bytes()),
+        ) {
+            converted.append(name, value);
+        }
+    }
+    converted
+}
+
+// Alt-Svc value pointing clients at the HTTP/3 listener
+pub fn alt_svc_value(addr: &SocketAddr) -> HeaderValue {
+    HeaderValue::from_str(&format!("h3=\":{}\"; ma=86400", addr.port())).unwrap()
+}
+
+// Advertises the HTTP/3 listener so clients can upgrade; replaces any
+// Alt-Svc from the backend, which would point past the balancer
+pub fn advertise(headers: &mut HeaderMap, alt_svc: Option<&HeaderValue>) {
+    if let Some(alt_svc) = alt_svc {
+        headers.insert(ALT_SVC, alt_svc.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LoadBalancerConfig;
+
+    #[test]
+    fn alt_svc_names_the_quic_port() {
+        let value = alt_svc_value(&"0.0.0.0:8443".parse().unwrap());
+        assert_eq!(value, "h3=\":8443\"; ma=86400");
+    }
+
+    #[test]
+    fn advertise_replaces_backend_alt_svc() {
+        let alt_svc = alt_svc_value(&"[::]:443".parse().unwrap());
+        let mut headers = HeaderMap::new();
+        headers.insert(ALT_SVC, HeaderValue::from_static("h2=\"backend:9000\""));
+
+        advertise(&mut headers, Some(&alt_svc));
+        assert_eq!(headers.get_all(ALT_SVC).iter().count(), 1);
+        assert_eq!(headers[ALT_SVC], alt_svc);
+    }
+
+    #[test]
+    fn nothing_advertised_without_http3() {
+        let mut headers = HeaderMap::new();
+        advertise(&mut headers, None);
+        assert!(headers.get(ALT_SVC).is_none());
+    }
+
+    #[test]
+    fn quic_listener_needs_tls_section() {
+        let mut config = serde_json::to_value(LoadBalancerConfig::default()).unwrap();
+        config["http3_listen_address"] = "0.0.0.0:443".into();
+        let err = serde_json::from_value::<LoadBalancerConfig>(config.clone()).unwrap_err();
+        assert!(err.to_string().contains("http3_listen_address"), "{}", err);
+
+        config
+            .as_object_mut()
+            .unwrap()
+            .remove("http3_listen_address");
+        config["tls"] = serde_json::json!({
+            "listen_address": "0.0.0.0:443",
+            "cert_path": "cert.pem",
+            "key_path": "key.pem",
+            "http3_listen_address": "0.0.0.0:443",
+        });
+        assert!(serde_json::from_value::<LoadBalancerConfig>(config).is_ok());
+    }
+}
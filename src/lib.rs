@@ -0,0 +1,27 @@
+// The load balancer as a library. The `load-balancer` binary is a thin
+// wrapper around it; other programs can embed the pools and request
+// handling, or plug their own `BalancingStrategy` into a pool.
+
+pub mod admin_listener;
+pub mod audit;
+pub mod backend_url;
+pub mod cli;
+pub mod client;
+pub mod config;
+pub mod filter;
+pub mod grpc;
+pub mod health_check;
+pub mod http3;
+pub mod load_balancer;
+pub mod mirror;
+pub mod reload;
+pub mod rewrite;
+pub mod router;
+pub mod split;
+pub mod strategy;
+pub mod tcp_proxy;
+pub mod tls;
+pub mod udp_proxy;
+
+pub use crate::load_balancer::{Backend, LoadBalancer, Pool};
+pub use crate::strategy::{BalancingStrategy, Outcome, Selection};
@@ -1,23 +1,181 @@
+pub mod admin;
+pub mod api;
 pub mod service;
 
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
 use std::time::{Duration, Instant};
 
+use arc_swap::ArcSwap;
+use dashmap::DashMap;
 use log::{info, warn};
+use serde::Serialize;
 
-use crate::config::{LoadBalancerConfig, Strategy};
+use crate::backend_url::BackendUrl;
+use crate::config::{
+    BackendConfig, BackendProtocol, HealthCheckConfig, LoadBalancerConfig, SlowStartConfig,
+    Strategy,
+};
+use crate::strategy::{self, BalancingStrategy, Outcome, Scope, Selection};
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum HealthStatus {
-    Healthy,
-    Unhealthy(u32),
+// Pool formed by the top-level `backends`
+pub const DEFAULT_POOL: &str = "default";
+
+// Weight of the newest sample in a backend's smoothed latency
+const LATENCY_SMOOTHING: f64 = 0.2;
+
+// Expired sticky sessions are swept at most this often
+const SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+// Instants kept in atomics, as nanoseconds since the first call plus one,
+// so that zero can stand for "never"
+fn ticks(instant: Instant) -> u64 {
+    instant.saturating_duration_since(epoch()).as_nanos() as u64 + 1
+}
+
+fn instant_from_ticks(ticks: u64) -> Option<Instant> {
+    (ticks != 0).then(|| epoch() + Duration::from_nanos(ticks - 1))
+}
+
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Runtime state of a backend. It is shared by every copy of the backend,
+// so it carries over when the pool's backend set is replaced.
+#[derive(Debug)]
+struct BackendStats {
+    healthy: AtomicBool,
+    // Consecutive failures since the last success
+    failures: AtomicU32,
+    // Open requests, connections or UDP flows
+    active: AtomicU32,
+    // Draining backends get no new requests or sessions and are removed
+    // once their last active request or connection ends
+    draining: AtomicBool,
+    // When the backend last became healthy, for slow start (ticks)
+    healthy_since: AtomicU64,
+    // Smoothed time until the response headers of proxied requests, in
+    // nanoseconds; zero until the first sample
+    latency: AtomicU64,
 }
 
+#[derive(Debug, Clone)]
 pub struct Backend {
     pub url: String,
-    pub health_status: HealthStatus,
+    // Parsed form of `url`, used to build upstream addresses
+    pub target: BackendUrl,
     pub weight: u32,
-    pub current_weight: i32,
+    pub protocol: BackendProtocol,
+    // Failover tier, 0 being the preferred one
+    pub priority: u32,
+    pub zone: Option<String>,
+    // Whether `zone` matches the balancer's own zone
+    pub local: bool,
+    stats: Arc<BackendStats>,
+}
+
+impl Backend {
+    fn new(
+        config: &BackendConfig,
+        local_zone: Option<&str>,
+        healthy_since: Option<Instant>,
+    ) -> Result<Self, String> {
+        Ok(Backend {
+            url: config.url.clone(),
+            target: BackendUrl::parse(&config.url)?,
+            weight: config.weight.unwrap_or(1),
+            protocol: config.protocol,
+            priority: config.priority,
+            zone: config.zone.clone(),
+            local: config.zone.is_some() && config.zone.as_deref() == local_zone,
+            stats: Arc::new(BackendStats {
+                healthy: AtomicBool::new(true),
+                failures: AtomicU32::new(0),
+                active: AtomicU32::new(0),
+                draining: AtomicBool::new(false),
+                healthy_since: AtomicU64::new(healthy_since.map_or(0, ticks)),
+                latency: AtomicU64::new(0),
+            }),
+        })
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.stats.healthy.load(Ordering::Relaxed)
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.stats.draining.load(Ordering::Relaxed)
+    }
+
+    // Whether new requests may be sent here
+    pub fn takes_traffic(&self) -> bool {
+        self.is_healthy() && !self.is_draining()
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.stats.failures.load(Ordering::Relaxed)
+    }
+
+    pub fn active_connections(&self) -> u32 {
+        self.stats.active.load(Ordering::Relaxed)
+    }
+
+    pub fn latency(&self) -> Option<Duration> {
+        match self.stats.latency.load(Ordering::Relaxed) {
+            0 => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    // When the backend last became healthy or was added at runtime; None
+    // for backends healthy since startup
+    pub fn healthy_since(&self) -> Option<Instant> {
+        instant_from_ticks(self.stats.healthy_since.load(Ordering::Relaxed))
+    }
+
+    pub fn state(&self) -> &'static str {
+        if self.is_draining() {
+            "draining"
+        } else if self.is_healthy() {
+            "healthy"
+        } else {
+            "unhealthy"
+        }
+    }
+
+    fn record_latency(&self, elapsed: Duration) {
+        let sample = elapsed.as_nanos().clamp(1, u64::MAX as u128) as u64;
+        let _ = self
+            .stats
+            .latency
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |latency| {
+                Some(match latency {
+                    0 => sample,
+                    latency => {
+                        let smoothed = latency as f64 * (1.0 - LATENCY_SMOOTHING)
+                            + sample as f64 * LATENCY_SMOOTHING;
+                        (smoothed as u64).max(1)
+                    }
+                })
+            });
+    }
+
+    fn config(&self) -> BackendConfig {
+        BackendConfig {
+            url: self.url.clone(),
+            weight: Some(self.weight),
+            protocol: self.protocol,
+            priority: self.priority,
+            zone: self.zone.clone(),
+        }
+    }
 }
 
 pub struct SessionInfo {
@@ -25,236 +183,1109 @@ pub struct SessionInfo {
     pub last_seen: Instant,
 }
 
-pub struct LoadBalancer {
-    // List of backend servers with metadata
-    pub backends: Vec<Backend>,
-    // Current index for simple round-robin selection
-    current_idx: usize,
-    // Maximum failures before considering a backend unhealthy
-    max_failures: u32,
-    // Current load balancing strategy
-    strategy: Strategy,
-    // Session sticky mapping (client IP -> backend)
-    sessions: HashMap<String, SessionInfo>,
-    // Session timeout in seconds
-    session_timeout: u64,
-    // Configuration
-    config: LoadBalancerConfig,
+// Sticky sessions of one pool (client address -> backend URL). They belong
+// to the pool rather than the strategy, so they survive strategy changes.
+pub struct Sessions {
+    map: DashMap<String, SessionInfo>,
+    timeout_seconds: AtomicU64,
+    // When expired sessions were last swept (ticks)
+    last_sweep: AtomicU64,
 }
 
-impl LoadBalancer {
-    pub fn new(backend_urls: Vec<String>, max_failures: u32, config: LoadBalancerConfig) -> Self {
-        let mut backends = Vec::new();
-
-        for url in backend_urls {
-            backends.push(Backend {
-                url,
-                health_status: HealthStatus::Healthy,
-                weight: 1,
-                current_weight: 0,
-            });
+impl Sessions {
+    fn new(timeout_seconds: u64) -> Self {
+        Sessions {
+            map: DashMap::new(),
+            timeout_seconds: AtomicU64::new(timeout_seconds),
+            last_sweep: AtomicU64::new(ticks(Instant::now())),
         }
+    }
 
-        LoadBalancer {
-            backends,
-            current_idx: 0,
-            max_failures,
-            strategy: Strategy::RoundRobin,
-            sessions: HashMap::new(),
-            session_timeout: config.session.timeout_seconds,
-            config,
-        }
-    }
-
-    pub fn new_weighted(
-        backends_with_weights: Vec<(String, u32)>,
-        max_failures: u32,
-        config: LoadBalancerConfig,
-    ) -> Self {
-        let mut backends = Vec::new();
-
-        for (url, weight) in backends_with_weights {
-            backends.push(Backend {
-                url,
-                health_status: HealthStatus::Healthy,
-                weight,
-                current_weight: 0,
-            });
+    pub fn timeout_seconds(&self) -> u64 {
+        self.timeout_seconds.load(Ordering::Relaxed)
+    }
+
+    fn is_fresh(&self, session: &SessionInfo, now: Instant) -> bool {
+        now.saturating_duration_since(session.last_seen).as_secs() < self.timeout_seconds()
+    }
+
+    // The client's backend, if it has a session that has not expired;
+    // the session's idle time starts over
+    pub fn lookup(&self, client: &str) -> Option<String> {
+        let now = Instant::now();
+        self.sweep_expired(now);
+
+        let mut session = self.map.get_mut(client)?;
+        if !self.is_fresh(&session, now) {
+            return None;
         }
+        session.last_seen = now;
+        Some(session.backend_url.clone())
+    }
 
-        LoadBalancer {
+    pub fn insert(&self, client: &str, backend_url: &str) {
+        self.map.insert(
+            client.to_string(),
+            SessionInfo {
+                backend_url: backend_url.to_string(),
+                last_seen: Instant::now(),
+            },
+        );
+    }
+
+    // Returns the backend the client was pinned to
+    pub fn remove(&self, client: &str) -> Option<String> {
+        self.map
+            .remove(client)
+            .map(|(_, session)| session.backend_url)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    // Live sessions as (client, backend URL, idle time)
+    pub fn list(&self) -> Vec<(String, String, Duration)> {
+        let now = Instant::now();
+        self.map
+            .iter()
+            .filter(|session| self.is_fresh(session, now))
+            .map(|session| {
+                (
+                    session.key().clone(),
+                    session.backend_url.clone(),
+                    now.saturating_duration_since(session.last_seen),
+                )
+            })
+            .collect()
+    }
+
+    fn clear(&self) {
+        self.map.clear();
+    }
+
+    fn remove_backend(&self, backend_url: &str) {
+        self.map.retain(|_, s| s.backend_url != backend_url);
+    }
+
+    fn set_timeout(&self, timeout_seconds: u64) {
+        self.timeout_seconds
+            .store(timeout_seconds, Ordering::Relaxed);
+    }
+
+    // Runs at most once per interval, from whichever request gets there first
+    fn sweep_expired(&self, now: Instant) {
+        let last_sweep = self.last_sweep.load(Ordering::Relaxed);
+        let due = instant_from_ticks(last_sweep)
+            .is_none_or(|last| now.saturating_duration_since(last) >= SESSION_SWEEP_INTERVAL);
+        if !due
+            || self
+                .last_sweep
+                .compare_exchange(last_sweep, ticks(now), Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+        {
+            return;
+        }
+
+        self.map.retain(|_, session| self.is_fresh(session, now));
+    }
+}
+
+// A pool's backends and settings at one point in time. Requests work from
+// a snapshot; changes build a new one and swap it in.
+#[derive(Clone)]
+pub struct PoolState {
+    pub backends: Vec<Arc<Backend>>,
+    // Configured load balancing strategy
+    strategy: Strategy,
+    // What picks the backends: the built-in implementation of `strategy`,
+    // or a custom one. Shared by every snapshot, so its state carries over.
+    balancer: Arc<dyn BalancingStrategy>,
+    // Maximum failures before considering a backend unhealthy
+    max_failures: u32,
+    // Health check settings for this pool
+    health_check: HealthCheckConfig,
+    // Headroom assumed per tier (and zone) before traffic spills over
+    overprovisioning_factor: f64,
+    // Zone the balancer runs in; same-zone backends are preferred
+    local_zone: Option<String>,
+    // Ramp-up of recovered backends' weight, if enabled
+    slow_start: Option<SlowStartConfig>,
+}
+
+impl PoolState {
+    fn find(&self, backend_url: &str) -> Option<&Arc<Backend>> {
+        self.backends.iter().find(|b| b.url == backend_url)
+    }
+}
+
+// A request, connection or flow counted as in flight on a backend. Dropping
+// it ends the count, so a task cancelled midway (e.g. when the client goes
+// away) does not leave the backend looking busy or keep it from draining.
+pub struct InFlight {
+    pool: Arc<Pool>,
+    backend: Arc<Backend>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.pool.connection_closed(&self.backend);
+    }
+}
+
+// A named group of backends with its own strategy, sessions and health
+// state. Selection reads a snapshot and atomics, so requests never wait on
+// each other; only admin changes and reloads take the update lock.
+pub struct Pool {
+    state: ArcSwap<PoolState>,
+    // Serializes changes to `state`, so concurrent updates are not lost
+    update_lock: Mutex<()>,
+    sessions: Sessions,
+}
+
+impl Pool {
+    pub fn from_config(
+        strategy: &Strategy,
+        backend_configs: &[BackendConfig],
+        health_check: HealthCheckConfig,
+        session_timeout: u64,
+    ) -> Result<Self, String> {
+        let backends = backend_configs
+            .iter()
+            .map(|config| Backend::new(config, None, None).map(Arc::new))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let balancer = strategy::from_config(strategy);
+        balancer.on_backends_change(&backends);
+        let state = PoolState {
             backends,
-            current_idx: 0,
-            max_failures,
-            strategy: Strategy::WeightedRoundRobin,
-            sessions: HashMap::new(),
-            session_timeout: config.session.timeout_seconds,
-            config,
+            strategy: strategy.clone(),
+            balancer,
+            max_failures: health_check.max_failures,
+            health_check,
+            overprovisioning_factor: 1.0,
+            local_zone: None,
+            slow_start: None,
+        };
+
+        Ok(Pool {
+            state: ArcSwap::from_pointee(state),
+            update_lock: Mutex::new(()),
+            sessions: Sessions::new(session_timeout),
+        })
+    }
+
+    pub fn snapshot(&self) -> Arc<PoolState> {
+        self.state.load_full()
+    }
+
+    // Applies `change` to a copy of the current state and swaps it in
+    fn update<R>(&self, change: impl FnOnce(&mut PoolState) -> R) -> R {
+        let _guard = lock(&self.update_lock);
+        let current = self.state.load_full();
+        let mut state = PoolState::clone(&current);
+        let result = change(&mut state);
+        if !Arc::ptr_eq(&state.balancer, &current.balancer)
+            || state.backends.len() != current.backends.len()
+            || state
+                .backends
+                .iter()
+                .zip(&current.backends)
+                .any(|(new, old)| !Arc::ptr_eq(new, old))
+        {
+            state.balancer.on_backends_change(&state.backends);
         }
+        self.state.store(Arc::new(state));
+        result
+    }
+
+    fn update_backend(&self, backend_url: &str, change: impl FnOnce(&mut Backend)) -> bool {
+        self.update(|state| {
+            match state.backends.iter_mut().find(|b| b.url == backend_url) {
+                Some(backend) => {
+                    // Copies the backend's settings; its stats stay shared
+                    change(Arc::make_mut(backend));
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+
+    pub fn health_check_config(&self) -> HealthCheckConfig {
+        self.state.load().health_check.clone()
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.state.load().strategy.clone()
     }
 
-    pub fn set_strategy(&mut self, strategy: Strategy) {
-        self.strategy = strategy;
+    pub fn backends(&self) -> Vec<Arc<Backend>> {
+        self.state.load().backends.clone()
     }
 
-    pub fn set_weight(&mut self, backend_url: &str, weight: u32) {
-        if let Some(backend) = self.backends.iter_mut().find(|b| b.url == backend_url) {
-            backend.weight = weight;
+    pub fn find(&self, backend_url: &str) -> Option<Arc<Backend>> {
+        self.state.load().find(backend_url).cloned()
+    }
+
+    pub fn sessions(&self) -> &Sessions {
+        &self.sessions
+    }
+
+    // Switches to the built-in implementation of `strategy`
+    pub fn set_strategy(&self, strategy: Strategy) {
+        self.update(|state| {
+            state.balancer = strategy::from_config(&strategy);
+            state.strategy = strategy;
+        });
+    }
+
+    // Hands backend selection to a custom strategy. The configured
+    // strategy is still reported, and a reload that keeps it keeps this one.
+    pub fn set_balancing_strategy(&self, balancer: Arc<dyn BalancingStrategy>) {
+        self.update(|state| state.balancer = balancer);
+    }
+
+    pub fn set_weight(&self, backend_url: &str, weight: u32) {
+        if self.update_backend(backend_url, |b| b.weight = weight) {
             info!("Set weight {} for backend {}", weight, backend_url);
         } else {
             warn!("Backend {} not found when setting weight", backend_url);
         }
     }
 
-    pub fn set_session_timeout(&mut self, timeout: u64) {
-        self.session_timeout = timeout;
+    pub fn set_priority(&self, backend_url: &str, priority: u32) {
+        if !self.update_backend(backend_url, |b| b.priority = priority) {
+            warn!("Backend {} not found when setting priority", backend_url);
+        }
+    }
+
+    pub fn set_zone(&self, backend_url: &str, zone: &str) {
+        let local = self.state.load().local_zone.as_deref() == Some(zone);
+        let found = self.update_backend(backend_url, |b| {
+            b.zone = Some(zone.to_string());
+            b.local = local;
+        });
+        if !found {
+            warn!("Backend {} not found when setting zone", backend_url);
+        }
+    }
+
+    pub fn set_local_zone(&self, zone: Option<String>) {
+        self.update(|state| {
+            for backend in &mut state.backends {
+                let local = backend.zone.is_some() && backend.zone == zone;
+                if backend.local != local {
+                    Arc::make_mut(backend).local = local;
+                }
+            }
+            state.local_zone = zone;
+        });
+    }
+
+    pub fn set_slow_start(&self, slow_start: Option<SlowStartConfig>) {
+        self.update(|state| state.slow_start = slow_start);
+    }
+
+    pub fn set_overprovisioning_factor(&self, factor: f64) -> Result<(), String> {
+        if !factor.is_finite() || factor < 1.0 {
+            return Err(format!(
+                "overprovisioning_factor must be at least 1.0, got {}",
+                factor
+            ));
+        }
+
+        self.update(|state| state.overprovisioning_factor = factor);
+        Ok(())
+    }
+
+    pub fn set_session_timeout(&self, timeout: u64) {
+        self.sessions.set_timeout(timeout);
         info!("Set session timeout to {} seconds", timeout);
     }
 
-    fn cleanup_expired_sessions(&mut self) {
-        self.sessions = self
-            .sessions
-            .drain()
-            .filter(|(_, session)| {
-                now.duration_since(session.last_seen).as_secs() < self.session_timeout
+    // Picks the tier to serve from. Each tier takes as much traffic as its
+    // healthy share (times the overprovisioning factor) allows, and the
+    // rest spills over to the next tier, e.g. from primaries to a DR site.
+    // Draining backends count as lost capacity, like unhealthy ones.
+    fn select_tier(state: &PoolState) -> Option<u32> {
+        let mut tiers: Vec<u32> = state.backends.iter().map(|b| b.priority).collect();
+        tiers.sort_unstable();
+        tiers.dedup();
+
+        let health: Vec<(u32, f64)> = tiers
+            .into_iter()
+            .map(|tier| {
+                let in_tier = state.backends.iter().filter(|b| b.priority == tier);
+                let total = in_tier.clone().count() as f64;
+                let healthy = in_tier.filter(|b| b.takes_traffic()).count() as f64;
+                let share = healthy / total * state.overprovisioning_factor * 100.0;
+                (tier, share.min(100.0))
             })
             .collect();
-    }
 
-    fn get_next_backend_round_robin(&mut self) -> Option<String> {
-        if self.backends.is_empty() {
+        let total_health = health.iter().map(|(_, h)| h).sum::<f64>().min(100.0);
+        if total_health <= 0.0 {
             return None;
         }
 
-        let start_idx = self.current_idx;
-        loop {
-            if let HealthStatus::Healthy = self.backends[self.current_idx].health_status {
-                let backend = self.backends[self.current_idx].url.clone();
-                self.current_idx = (self.current_idx + 1) % self.backends.len();
-                return Some(backend);
-            }
-
-            self.current_idx = (self.current_idx + 1) % self.backends.len();
+        // Only draw when traffic is actually spread over several tiers
+        let point = if health[0].1 >= 100.0 {
+            0.0
+        } else {
+            fastrand::f64() * total_health
+        };
 
-            if self.current_idx == start_idx {
-                return None;
+        let mut remaining = 100.0;
+        let mut upper = 0.0;
+        for (tier, tier_health) in &health {
+            let load = tier_health.min(remaining);
+            remaining -= load;
+            upper += load;
+            if point < upper {
+                return Some(*tier);
             }
         }
-    }
 
-    fn get_next_backend_weighted(&mut self) -> Option<String> {
-        let has_healthy = self
-            .backends
+        health
             .iter()
-            .any(|b| matches!(b.health_status, HealthStatus::Healthy));
-        if !has_healthy {
+            .rev()
+            .find(|(_, h)| *h > 0.0)
+            .map(|(tier, _)| *tier)
+    }
+
+    // Within a tier, keeps traffic in the balancer's own zone while that
+    // zone has enough healthy capacity, and sends the shortfall to other
+    // zones in proportion, like the spill between tiers.
+    fn select_locality(state: &PoolState, tier: u32) -> Option<bool> {
+        state.local_zone.as_ref()?;
+
+        let in_tier = || state.backends.iter().filter(|b| b.priority == tier);
+
+        let local_total = in_tier().filter(|b| b.local).count();
+        if local_total == 0 {
             return None;
         }
+        let local_healthy = in_tier().filter(|b| b.local && b.takes_traffic()).count();
+        let remote_healthy = in_tier().filter(|b| !b.local && b.takes_traffic()).count();
 
-        let mut total = 0;
-        let mut best_idx = 0;
-        let mut best_weight = -1;
-        for (i, backend) in self.backends.iter_mut().enumerate() {
-            if matches!(backend.health_status, HealthStatus::Healthy) {
-                total += backend.weight as i32;
-                backend.current_weight += backend.weight as i32;
+        if remote_healthy == 0 {
+            return Some(true);
+        }
+        if local_healthy == 0 {
+            return Some(false);
+        }
 
-                if backend.current_weight > best_weight {
-                    best_weight = backend.current_weight;
-                    best_idx = i;
-                }
-            }
+        let share = local_healthy as f64 / local_total as f64 * state.overprovisioning_factor;
+        Some(share >= 1.0 || fastrand::f64() < share)
+    }
+
+    pub fn get_next_backend(&self, client_ip: Option<&str>) -> Option<Arc<Backend>> {
+        let state = self.state.load();
+        let tier = Self::select_tier(&state)?;
+        let scope = Scope {
+            tier,
+            local: Self::select_locality(&state, tier),
+        };
+
+        let selection = Selection::new(
+            &state.backends,
+            client_ip,
+            &self.sessions,
+            scope,
+            state.slow_start.as_ref(),
+        );
+        state.balancer.select(&selection)
+    }
+
+    pub fn mark_unhealthy(&self, backend: &Backend) {
+        let state = self.state.load();
+        let max_failures = state.max_failures.max(1);
+        let failures = backend.stats.failures.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if failures >= max_failures && backend.stats.healthy.swap(false, Ordering::AcqRel) {
+            warn!(
+                "Backend {} marked as unhealthy ({} failures)",
+                backend.url, failures
+            );
+            state.balancer.on_health_change(backend, false);
+        } else if backend.is_healthy() {
+            warn!(
+                "Backend {} failed ({}/{} failures)",
+                backend.url, failures, max_failures
+            );
+        } else {
+            warn!(
+                "Backend {} remains unhealthy ({} failures)",
+                backend.url, failures
+            );
         }
+    }
 
-        if best_weight < 0 {
-            return None;
+    // Called after every successful request, so it only writes when
+    // something changes
+    pub fn mark_healthy(&self, backend: &Backend) {
+        if backend.stats.failures.load(Ordering::Relaxed) != 0 {
+            backend.stats.failures.store(0, Ordering::Relaxed);
         }
+        if !backend.is_healthy() && !backend.stats.healthy.swap(true, Ordering::AcqRel) {
+            backend
+                .stats
+                .healthy_since
+                .store(ticks(Instant::now()), Ordering::Relaxed);
+            info!("Backend {} marked as healthy", backend.url);
+            self.state.load().balancer.on_health_change(backend, true);
+        }
+    }
+
+    // Counts a request or connection to `backend` as in flight until the
+    // returned guard is dropped
+    pub fn in_flight(self: &Arc<Self>, backend: &Arc<Backend>) -> InFlight {
+        self.connection_opened(backend);
+        InFlight {
+            pool: self.clone(),
+            backend: backend.clone(),
+        }
+    }
 
-        self.backends[best_idx].current_weight -= total;
+    // In flight until the response (or, for gRPC, the call) ends; returns
+    // the new active count
+    pub fn connection_opened(&self, backend: &Backend) -> u32 {
+        let active = backend.stats.active.fetch_add(1, Ordering::Relaxed) + 1;
+        self.state.load().balancer.on_request_start(backend);
+        active
+    }
 
-        Some(self.backends[best_idx].url.clone())
+    // Latency feeds the backend's smoothed latency
+    pub fn record_outcome(&self, backend: &Backend, outcome: Outcome) {
+        if let Outcome::Success { latency } = outcome {
+            backend.record_latency(latency);
+        }
+        self.state.load().balancer.on_response(backend, outcome);
     }
 
-    fn get_backend_for_client(&mut self, client_ip: &str) -> Option<String> {
-        self.cleanup_expired_sessions();
+    pub fn connection_closed(&self, backend: &Backend) -> u32 {
+        let active = backend
+            .stats
+            .active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                Some(n.saturating_sub(1))
+            })
+            .map_or(0, |previous| previous.saturating_sub(1));
 
-        if let Some(session) = self.sessions.get_mut(client_ip) {
-            if let Some(backend) = self.backends.iter().find(|b| b.url == session.backend_url) {
-                if matches!(backend.health_status, HealthStatus::Healthy) {
-                    session.last_seen = Instant::now();
-                    return Some(session.backend_url.clone());
-                }
+        if active == 0 && backend.is_draining() {
+            info!("Backend {} drained", backend.url);
+            self.remove_drained(backend);
+        }
+
+        active
+    }
+
+    // Removes this very backend, not a newer one added under the same URL
+    fn remove_drained(&self, backend: &Backend) {
+        let removed = self.update(|state| {
+            let before = state.backends.len();
+            state
+                .backends
+                .retain(|b| !Arc::ptr_eq(&b.stats, &backend.stats));
+            state.backends.len() != before
+        });
+        if removed {
+            info!("Removed backend {}", backend.url);
+        }
+    }
+
+    // New backends start in slow start, if the pool has it enabled
+    pub fn add_backend(&self, config: &BackendConfig) -> Result<(), String> {
+        self.update(|state| {
+            if state.find(&config.url).is_some() {
+                return Err(format!("Backend {} already exists", config.url));
             }
 
-            self.sessions.remove(client_ip);
+            let backend = Backend::new(config, state.local_zone.as_deref(), Some(Instant::now()))?;
+            state.backends.push(Arc::new(backend));
+            Ok(())
+        })?;
+        info!("Added backend {}", config.url);
+
+        Ok(())
+    }
+
+    pub fn remove_backend(&self, backend_url: &str) -> bool {
+        let removed = self.update(|state| {
+            let before = state.backends.len();
+            state.backends.retain(|b| b.url != backend_url);
+            state.backends.len() != before
+        });
+        if !removed {
+            return false;
         }
 
-        let backend_url = match self.strategy {
-            Strategy::StickySession => self.get_next_backend_weighted(),
-            Strategy::WeightedRoundRobin => self.get_next_backend_weighted(),
-            Strategy::RoundRobin => self.get_next_backend_round_robin(),
-        };
+        self.sessions.remove_backend(backend_url);
+        info!("Removed backend {}", backend_url);
 
-        if let Some(url) = backend_url.clone() {
-            self.sessions.insert(
-                client_ip.to_string(),
-                SessionInfo {
-                    backend_url: url,
-                    last_seen: Instant::now(),
-                },
-            );
+        true
+    }
+
+    // Stops sending new work to a backend. Returns its active count, or
+    // None if it does not exist; at zero it is removed right away.
+    pub fn drain_backend(&self, backend_url: &str) -> Option<u32> {
+        let backend = self.state.load().find(backend_url)?.clone();
+        backend.stats.draining.store(true, Ordering::Release);
+        let active = backend.active_connections();
+        self.sessions.remove_backend(backend_url);
+
+        if active == 0 {
+            info!("Backend {} drained", backend_url);
+            self.remove_drained(&backend);
+        } else {
+            info!("Draining backend {} ({} still active)", backend_url, active);
         }
 
-        backend_url
+        Some(active)
     }
 
-    pub fn get_next_backend(&mut self, client_ip: Option<&str>) -> Option<String> {
-        match self.strategy {
-            Strategy::RoundRobin => self.get_next_backend_round_robin(),
-            Strategy::WeightedRoundRobin => self.get_next_backend_weighted(),
-            Strategy::StickySession => {
-                if let Some(ip) = client_ip {
-                    self.get_backend_for_client(ip)
+    pub fn clear_sessions(&self) {
+        self.sessions.clear();
+        info!("Cleared all sessions");
+    }
+
+    // Backends as they are configured right now; draining ones are left
+    // out since they are about to go away
+    pub fn backend_configs(&self) -> Vec<BackendConfig> {
+        self.state
+            .load()
+            .backends
+            .iter()
+            .filter(|b| !b.is_draining())
+            .map(|b| b.config())
+            .collect()
+    }
+
+    // Takes over the settings and backends of `new`, a pool built from a
+    // reloaded config. Backends in both keep their health state, counters,
+    // sessions and draining flag; removed ones are drained so in-flight
+    // requests finish.
+    fn update_from(&self, new: &Pool) -> (usize, usize) {
+        let new_state = new.snapshot();
+        let mut added = 0;
+        let mut removed = Vec::new();
+
+        self.update(|state| {
+            let mut backends = Vec::with_capacity(new_state.backends.len());
+            for backend in &new_state.backends {
+                match state.find(&backend.url) {
+                    Some(existing) => {
+                        // A draining backend keeps draining; being listed
+                        // again does not undo an admin's drain
+                        backends.push(Arc::new(Backend {
+                            stats: existing.stats.clone(),
+                            ..Backend::clone(backend)
+                        }));
+                    }
+                    None => {
+                        backend
+                            .stats
+                            .healthy_since
+                            .store(ticks(Instant::now()), Ordering::Relaxed);
+                        backends.push(backend.clone());
+                        added += 1;
+                    }
+                }
+            }
+
+            // Removed backends stay, draining, until their last request ends
+            for backend in &state.backends {
+                if new_state.find(&backend.url).is_some() {
+                    continue;
+                }
+                if !backend.stats.draining.swap(true, Ordering::AcqRel) {
+                    removed.push(backend.url.clone());
+                }
+                if backend.active_connections() > 0 {
+                    backends.push(backend.clone());
                 } else {
-                    self.get_next_backend_weighted()
+                    info!("Removed backend {}", backend.url);
                 }
             }
+
+            // An unchanged strategy keeps its state, e.g. its cursor
+            let balancer = if state.strategy == new_state.strategy {
+                state.balancer.clone()
+            } else {
+                new_state.balancer.clone()
+            };
+            *state = PoolState {
+                backends,
+                balancer,
+                ..PoolState::clone(&new_state)
+            };
+        });
+
+        self.sessions.set_timeout(new.sessions.timeout_seconds());
+        for url in &removed {
+            self.sessions.remove_backend(url);
         }
+
+        (added, removed.len())
     }
+}
 
-    pub fn mark_unhealthy(&mut self, backend_url: &str) {
-        if let Some(backend) = self.backends.iter_mut().find(|b| b.url == backend_url) {
-            match &backend.health_status {
-                HealthStatus::Healthy => {
-                    backend.health_status = HealthStatus::Unhealthy(1);
-                    warn!("Backend {} marked as unhealthy (1 failure)", backend_url);
-                }
-                HealthStatus::Unhealthy(failures) => {
-                    let new_failures = failures + 1;
-                    backend.health_status = HealthStatus::Unhealthy(new_failures);
-                    warn!(
-                        "Backend {} remains unhealthy ({} failures)",
-                        backend_url, new_failures
-                    );
-                }
+pub struct LoadBalancer {
+    // Named backend pools, including the default pool. The map is swapped
+    // as a whole when a reload adds or removes pools.
+    pools: ArcSwap<HashMap<String, Arc<Pool>>>,
+    // Configuration as last loaded; also serializes reloads
+    config: Mutex<LoadBalancerConfig>,
+}
+
+impl LoadBalancer {
+    pub fn new(config: LoadBalancerConfig) -> Result<Self, String> {
+        let session_timeout = config.session.timeout_seconds;
+        let mut pools = HashMap::new();
+
+        let default_pool = Pool::from_config(
+            &config.strategy,
+            &config.backends,
+            config.health_check.clone(),
+            session_timeout,
+        )
+        .and_then(|pool| {
+            pool.set_overprovisioning_factor(config.overprovisioning_factor)?;
+            pool.set_local_zone(config.zone.clone());
+            pool.set_slow_start(config.slow_start.clone());
+            Ok(pool)
+        })
+        .map_err(|e| format!("Pool '{}': {}", DEFAULT_POOL, e))?;
+        pools.insert(DEFAULT_POOL.to_string(), Arc::new(default_pool));
+
+        for (name, pool_config) in &config.pools {
+            if name == DEFAULT_POOL {
+                return Err(format!("Pool name '{}' is reserved", DEFAULT_POOL));
             }
+
+            let health_check = pool_config
+                .health_check
+                .clone()
+                .unwrap_or_else(|| config.health_check.clone());
+            let pool = Pool::from_config(
+                &pool_config.strategy,
+                &pool_config.backends,
+                health_check,
+                session_timeout,
+            )
+            .and_then(|pool| {
+                pool.set_overprovisioning_factor(pool_config.overprovisioning_factor)?;
+                pool.set_local_zone(config.zone.clone());
+                pool.set_slow_start(pool_config.slow_start.clone());
+                Ok(pool)
+            })
+            .map_err(|e| format!("Pool '{}': {}", name, e))?;
+            pools.insert(name.clone(), Arc::new(pool));
+            info!(
+                "Created pool '{}' with {} backends ({:?})",
+                name,
+                pool_config.backends.len(),
+                pool_config.strategy
+            );
         }
+
+        for (name, pool) in &pools {
+            for backend in pool.backends() {
+                backend
+                    .target
+                    .validate_for(config.mode)
+                    .map_err(|e| format!("Pool '{}': {}", name, e))?;
+            }
+        }
+
+        Ok(LoadBalancer {
+            pools: ArcSwap::from_pointee(pools),
+            config: Mutex::new(config),
+        })
     }
-    pub fn mark_healthy(&mut self, backend_url: &str) {
-        if let Some(backend) = self.backends.iter_mut().find(|b| b.url == backend_url) {
-            match &backend.health_status {
-                HealthStatus::Healthy => {
-                    // Already healthy, do nothing
+
+    // Applies a reloaded config. The new config is validated in full
+    // before anything changes, so an invalid one leaves the running state
+    // as it was. Returns the names of new pools, which need health checkers.
+    pub fn reload(&self, mut config: LoadBalancerConfig) -> Result<Vec<String>, String> {
+        let mut running = lock(&self.config);
+        keep_listener_settings(&running, &mut config);
+        let reloaded = LoadBalancer::new(config.clone())?;
+
+        let current = self.pools.load_full();
+        for name in current.keys() {
+            if !reloaded.pools.load().contains_key(name) {
+                info!("Removed pool '{}'", name);
+            }
+        }
+
+        let mut pools = HashMap::new();
+        let mut added_pools = Vec::new();
+        for (name, pool) in reloaded.pools.load().iter() {
+            match current.get(name) {
+                Some(existing) => {
+                    let (added, removed) = existing.update_from(pool);
+                    info!(
+                        "Reloaded pool '{}': {} backends added, {} removed",
+                        name, added, removed
+                    );
+                    pools.insert(name.clone(), existing.clone());
                 }
-                HealthStatus::Unhealthy(_) => {
-                    backend.health_status = HealthStatus::Healthy;
-                    info!("Backend {} marked as healthy", backend_url);
+                None => {
+                    info!("Added pool '{}'", name);
+                    pools.insert(name.clone(), pool.clone());
+                    added_pools.push(name.clone());
                 }
             }
         }
+
+        self.pools.store(Arc::new(pools));
+        *running = config;
+        Ok(added_pools)
     }
 
-    pub fn get_all_backends(&self) -> Vec<String> {
-        self.backends.iter().map(|b| b.url.clone()).collect()
+    pub fn pool(&self, name: &str) -> Option<Arc<Pool>> {
+        self.pools.load().get(name).cloned()
+    }
+
+    pub fn add_backend(&self, pool_name: &str, backend: &BackendConfig) -> Result<(), String> {
+        let mode = lock(&self.config).mode;
+        let url = BackendUrl::parse(&backend.url)?;
+        url.validate_for(mode)?;
+        url.validate_protocol(backend.protocol)?;
+        self.pool(pool_name)
+            .ok_or_else(|| format!("Unknown pool '{}'", pool_name))?
+            .add_backend(backend)
+    }
+
+    pub fn pool_names(&self) -> Vec<String> {
+        self.pools.load().keys().cloned().collect()
+    }
+
+    // The loaded configuration with the changes made at runtime applied
+    pub fn current_config(&self) -> LoadBalancerConfig {
+        let mut config = lock(&self.config).clone();
+        let pools = self.pools.load();
+
+        if let Some(pool) = pools.get(DEFAULT_POOL) {
+            let state = pool.snapshot();
+            config.strategy = state.strategy.clone();
+            config.backends = pool.backend_configs();
+            config.overprovisioning_factor = state.overprovisioning_factor;
+            config.session.timeout_seconds = pool.sessions.timeout_seconds();
+        }
+        for (name, pool_config) in &mut config.pools {
+            if let Some(pool) = pools.get(name) {
+                let state = pool.snapshot();
+                pool_config.strategy = state.strategy.clone();
+                pool_config.backends = pool.backend_configs();
+                pool_config.overprovisioning_factor = state.overprovisioning_factor;
+            }
+        }
+
+        config
+    }
+}
+
+// Listeners are bound at startup, so their settings cannot change on a
+// reload; the running values are kept
+fn keep_listener_settings(running: &LoadBalancerConfig, config: &mut LoadBalancerConfig) {
+    fn differs<T: Serialize>(a: &T, b: &T) -> bool {
+        serde_json::to_value(a).ok() != serde_json::to_value(b).ok()
+    }
+
+    let mut kept = Vec::new();
+    if config.listen_address != running.listen_address {
+        config.listen_address = running.listen_address.clone();
+        kept.push("listen_address");
+    }
+    if config.mode != running.mode {
+        config.mode = running.mode;
+        kept.push("mode");
+    }
+    if differs(&config.tls, &running.tls) {
+        config.tls = running.tls.clone();
+        kept.push("tls");
+    }
+    if differs(&config.admin, &running.admin) {
+        config.admin = running.admin.clone();
+        kept.push("admin");
+    }
+    if differs(&config.tcp, &running.tcp) {
+        config.tcp = running.tcp.clone();
+        kept.push("tcp");
+    }
+    if differs(&config.udp, &running.udp) {
+        config.udp = running.udp.clone();
+        kept.push("udp");
+    }
+    if differs(&config.reload, &running.reload) {
+        config.reload = running.reload.clone();
+        kept.push("reload");
+    }
+
+    if !kept.is_empty() {
+        warn!(
+            "Changes to {} need a restart and were not applied",
+            kept.join(", ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Backends given as (url, priority); one failure marks them unhealthy
+    fn pool(backends: &[(&str, u32)]) -> Arc<Pool> {
+        let configs: Vec<BackendConfig> = backends
+            .iter()
+            .map(|(url, priority)| BackendConfig {
+                url: url.to_string(),
+                weight: None,
+                protocol: BackendProtocol::Http1,
+                priority: *priority,
+                zone: None,
+            })
+            .collect();
+        let mut health_check = LoadBalancerConfig::default().health_check;
+        health_check.max_failures = 1;
+        Arc::new(Pool::from_config(&Strategy::RoundRobin, &configs, health_check, 300).unwrap())
+    }
+
+    fn fail(pool: &Pool, url: &str) {
+        let backend = pool.snapshot().find(url).unwrap().clone();
+        pool.mark_unhealthy(&backend);
+    }
+
+    fn tier(pool: &Pool) -> Option<u32> {
+        Pool::select_tier(&pool.snapshot())
+    }
+
+    #[test]
+    fn healthy_primaries_take_everything() {
+        let pool = pool(&[("http://a", 0), ("http://b", 0), ("http://dr", 1)]);
+        for _ in 0..100 {
+            assert_eq!(tier(&pool), Some(0));
+        }
+    }
+
+    #[test]
+    fn fails_over_when_primaries_are_down() {
+        let pool = pool(&[("http://a", 0), ("http://b", 0), ("http://dr", 1)]);
+        fail(&pool, "http://a");
+        fail(&pool, "http://b");
+        assert_eq!(tier(&pool), Some(1));
+
+        fail(&pool, "http://dr");
+        assert_eq!(tier(&pool), None);
+    }
+
+    #[test]
+    fn spills_shortfall_to_next_tier() {
+        let pool = pool(&[
+            ("http://a", 0),
+            ("http://b", 0),
+            ("http://c", 0),
+            ("http://dr", 1),
+        ]);
+        pool.set_overprovisioning_factor(1.4).unwrap();
+        fail(&pool, "http://a");
+
+        // 2 of 3 healthy at 1.4 keeps ~93% on the primaries
+        let primary = (0..10_000).filter(|_| tier(&pool) == Some(0)).count();
+        assert!((9_000..9_600).contains(&primary), "{}", primary);
+    }
+
+    #[test]
+    fn overprovisioning_absorbs_small_losses() {
+        let pool = pool(&[
+            ("http://a", 0),
+            ("http://b", 0),
+            ("http://c", 0),
+            ("http://d", 0),
+            ("http://dr", 1),
+        ]);
+        pool.set_overprovisioning_factor(1.4).unwrap();
+        fail(&pool, "http://a");
+
+        // 3 of 4 healthy at 1.4 is still above 100%
+        for _ in 0..100 {
+            assert_eq!(tier(&pool), Some(0));
+        }
+    }
+
+    #[test]
+    fn draining_backends_are_not_capacity() {
+        let pool = pool(&[("http://a", 0), ("http://dr", 1)]);
+        let backend = pool.snapshot().find("http://a").unwrap().clone();
+        let _in_flight = pool.in_flight(&backend);
+        pool.drain_backend("http://a");
+
+        assert!(backend.is_healthy());
+        assert_eq!(tier(&pool), Some(1));
+    }
+
+    // Puts each backend in a zone and runs the balancer in "local"
+    fn zoned(pool: &Pool, zones: &[(&str, &str)]) {
+        for (url, zone) in zones {
+            pool.set_zone(url, zone);
+        }
+        pool.set_local_zone(Some("local".to_string()));
+    }
+
+    fn locality(pool: &Pool) -> Option<bool> {
+        Pool::select_locality(&pool.snapshot(), 0)
+    }
+
+    #[test]
+    fn healthy_local_zone_takes_everything() {
+        let pool = pool(&[("http://a", 0), ("http://b", 0), ("http://c", 0)]);
+        zoned(
+            &pool,
+            &[
+                ("http://a", "local"),
+                ("http://b", "local"),
+                ("http://c", "remote"),
+            ],
+        );
+        for _ in 0..100 {
+            assert_eq!(locality(&pool), Some(true));
+            assert_ne!(pool.get_next_backend(None).unwrap().url, "http://c");
+        }
+    }
+
+    #[test]
+    fn spills_local_shortfall_to_other_zones() {
+        let pool = pool(&[("http://a", 0), ("http://b", 0), ("http://c", 0)]);
+        zoned(
+            &pool,
+            &[
+                ("http://a", "local"),
+                ("http://b", "local"),
+                ("http://c", "remote"),
+            ],
+        );
+        pool.set_overprovisioning_factor(1.4).unwrap();
+        fail(&pool, "http://a");
+
+        // 1 of 2 healthy at 1.4 keeps ~70% in the local zone
+        let local = (0..10_000)
+            .filter(|_| locality(&pool) == Some(true))
+            .count();
+        assert!((6_500..7_500).contains(&local), "{}", local);
+
+        pool.set_overprovisioning_factor(1.0).unwrap();
+        let local = (0..10_000)
+            .filter(|_| locality(&pool) == Some(true))
+            .count();
+        assert!((4_500..5_500).contains(&local), "{}", local);
+
+        fail(&pool, "http://b");
+        assert_eq!(locality(&pool), Some(false));
+        assert_eq!(pool.get_next_backend(None).unwrap().url, "http://c");
+    }
+
+    #[test]
+    fn zones_are_ignored_without_local_backends() {
+        let pool = pool(&[("http://a", 0), ("http://b", 0)]);
+        assert_eq!(locality(&pool), None);
+
+        zoned(&pool, &[("http://a", "remote"), ("http://b", "remote")]);
+        assert_eq!(locality(&pool), None);
+
+        // Local backends stay in use while no other zone can take traffic
+        pool.set_zone("http://a", "local");
+        fail(&pool, "http://b");
+        assert_eq!(locality(&pool), Some(true));
+    }
+
+    fn reloadable(backends: &[&str]) -> LoadBalancerConfig {
+        LoadBalancerConfig {
+            backends: backends
+                .iter()
+                .map(|url| BackendConfig {
+                    url: url.to_string(),
+                    weight: None,
+                    protocol: BackendProtocol::Http1,
+                    priority: 0,
+                    zone: None,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn find(lb: &LoadBalancer, url: &str) -> Option<Arc<Backend>> {
+        lb.pool(DEFAULT_POOL)?.snapshot().find(url).cloned()
+    }
+
+    #[test]
+    fn reload_keeps_backend_stats() {
+        let lb = LoadBalancer::new(reloadable(&["http://a", "http://b"])).unwrap();
+        let pool = lb.pool(DEFAULT_POOL).unwrap();
+        let a = find(&lb, "http://a").unwrap();
+        let in_flight = pool.in_flight(&a);
+        for _ in 0..3 {
+            pool.mark_unhealthy(&a);
+        }
+        assert!(!a.is_healthy());
+
+        let mut config = reloadable(&["http://a", "http://c"]);
+        config.backends[0].weight = Some(7);
+        lb.reload(config).unwrap();
+
+        let reloaded = find(&lb, "http://a").unwrap();
+        assert_eq!(reloaded.weight, 7);
+        assert!(!reloaded.is_healthy());
+        assert_eq!(reloaded.failures(), 3);
+        assert_eq!(reloaded.active_connections(), 1);
+        drop(in_flight);
+        assert_eq!(reloaded.active_connections(), 0);
+
+        assert!(find(&lb, "http://b").is_none());
+        assert!(find(&lb, "http://c").unwrap().is_healthy());
+        // The very same pool is kept, so its sessions are too
+        assert!(Arc::ptr_eq(&pool, &lb.pool(DEFAULT_POOL).unwrap()));
+    }
+
+    #[test]
+    fn reload_keeps_draining_backends_draining() {
+        let lb = LoadBalancer::new(reloadable(&["http://a", "http://b"])).unwrap();
+        let pool = lb.pool(DEFAULT_POOL).unwrap();
+        let a = find(&lb, "http://a").unwrap();
+        let _in_flight = pool.in_flight(&a);
+        assert_eq!(pool.drain_backend("http://a"), Some(1));
+
+        lb.reload(reloadable(&["http://a", "http://b"])).unwrap();
+
+        let reloaded = find(&lb, "http://a").unwrap();
+        assert!(reloaded.is_draining());
+        assert_eq!(reloaded.state(), "draining");
+        for _ in 0..100 {
+            assert_eq!(pool.get_next_backend(None).unwrap().url, "http://b");
+        }
+    }
+
+    #[test]
+    fn reload_keeps_listener_settings() {
+        let lb = LoadBalancer::new(reloadable(&["http://a"])).unwrap();
+
+        let mut config = reloadable(&["http://a", "http://b"]);
+        config.listen_address = "127.0.0.1:9999".to_string();
+        config.udp.max_flows += 1;
+        lb.reload(config).unwrap();
+
+        let running = lb.current_config();
+        let default = LoadBalancerConfig::default();
+        assert_eq!(running.listen_address, default.listen_address);
+        assert_eq!(running.udp.max_flows, default.udp.max_flows);
+        // Everything else is taken from the new config
+        assert_eq!(running.backends.len(), 2);
     }
 }
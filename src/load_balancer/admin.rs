@@ -0,0 +1,381 @@
+use hyper::body::Body;
+use hyper::{Request, Response, StatusCode};
+use log::info;
+use serde_json::{Value, json};
+
+use crate::audit::AuditLog;
+use crate::config::{BackendConfig, BackendProtocol, Strategy};
+use crate::load_balancer::{DEFAULT_POOL, LoadBalancer, api};
+use crate::router::Router;
+
+// What an admin request acts on, and who it is attributed to in the audit log
+pub struct AdminContext<'a> {
+    pub lb: &'a LoadBalancer,
+    pub router: &'a Router,
+    pub audit: &'a AuditLog,
+    pub who: &'a str,
+}
+
+impl AdminContext<'_> {
+    pub fn record(&self, action: &str, old: Value, new: Value) {
+        self.audit.record(self.who, action, old, new);
+    }
+}
+
+fn query_param<'a>(req: &'a Request<Body>, name: &str) -> Option<&'a str> {
+    req.uri()
+        .query()?
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+// Admin calls may name a backend by URL or, as before, by host:port
+fn admin_backend_url(value: &str) -> String {
+    if value.contains("://") {
+        value.to_string()
+    } else {
+        format!("http://{}", value)
+    }
+}
+
+fn admin_error_response(status: StatusCode, message: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(message))
+        .unwrap()
+}
+
+fn unknown_pool_response(pool_name: &str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::from(format!("Unknown pool '{}'", pool_name)))
+        .unwrap()
+}
+
+fn unknown_backend_response(backend_url: &str) -> Response<Body> {
+    admin_error_response(
+        StatusCode::NOT_FOUND,
+        format!("Unknown backend {}", backend_url),
+    )
+}
+
+// Handles everything under /admin: the query-string endpoints and the
+// JSON API. Changes are recorded in the audit log.
+pub async fn handle(req: Request<Body>, ctx: &AdminContext<'_>) -> Response<Body> {
+    if req.uri().path().starts_with(api::API_PREFIX) {
+        return api::handle(req, ctx).await;
+    }
+
+    // Admin endpoints act on the pool given by `pool=`, or the default pool
+    let admin_pool = query_param(&req, "pool").unwrap_or(DEFAULT_POOL);
+
+    if req.uri().path() == "/admin/strategy" {
+        let strategy = match query_param(&req, "type") {
+            Some("weighted") => Some((Strategy::WeightedRoundRobin, "Weighted Round Robin")),
+            Some("roundrobin") => Some((Strategy::RoundRobin, "Round Robin")),
+            Some("sticky") => Some((Strategy::StickySession, "Sticky Session")),
+            _ => None,
+        };
+
+        if let Some((strategy, name)) = strategy {
+            let Some(pool) = ctx.lb.pool(admin_pool) else {
+                return unknown_pool_response(admin_pool);
+            };
+            ctx.record(
+                "pool.strategy",
+                json!({ "pool": admin_pool, "strategy": pool.strategy() }),
+                json!({ "pool": admin_pool, "strategy": strategy }),
+            );
+            pool.set_strategy(strategy);
+            info!(
+                "Changed load balancing strategy of pool '{}' to {}",
+                admin_pool, name
+            );
+            return Response::new(Body::from(format!(
+                "Strategy of pool '{}' changed to {}",
+                admin_pool, name
+            )));
+        }
+    }
+
+    if req.uri().path() == "/admin/weight" {
+        let weight = query_param(&req, "weight").and_then(|w| w.parse::<u32>().ok());
+
+        if let (Some(backend), Some(weight)) = (query_param(&req, "backend"), weight) {
+            let backend_url = admin_backend_url(backend);
+            let Some(pool) = ctx.lb.pool(admin_pool) else {
+                return unknown_pool_response(admin_pool);
+            };
+            let Some(old) = pool.find(&backend_url).map(|b| b.weight) else {
+                return unknown_backend_response(&backend_url);
+            };
+            ctx.record(
+                "backend.weight",
+                json!({ "pool": admin_pool, "backend": backend_url, "weight": old }),
+                json!({ "pool": admin_pool, "backend": backend_url, "weight": weight }),
+            );
+            pool.set_weight(&backend_url, weight);
+            return Response::new(Body::from(format!(
+                "Weight for {} in pool '{}' set to {}",
+                backend, admin_pool, weight
+            )));
+        }
+    }
+
+    if req.uri().path() == "/admin/split" {
+        let route = query_param(&req, "route");
+        let weight = query_param(&req, "weight").and_then(|w| w.parse::<u32>().ok());
+
+        if let (Some(route), Some(pool), Some(weight)) = (route, query_param(&req, "pool"), weight)
+        {
+            let old = ctx.router.split_weights(route).unwrap_or_default();
+            let total: u64 = old
+                .iter()
+                .map(|(name, old_weight)| if name == pool { weight } else { *old_weight })
+                .map(u64::from)
+                .sum();
+            if !old.is_empty() && total == 0 {
+                return admin_error_response(
+                    StatusCode::BAD_REQUEST,
+                    "Split needs at least one pool with a weight above 0".to_string(),
+                );
+            }
+            let response = match ctx.router.set_split_weight(route, pool, weight) {
+                Ok(weights) => {
+                    ctx.record(
+                        "route.split",
+                        json!({ "route": route, "split": api::split_targets(&old) }),
+                        json!({ "route": route, "split": api::split_targets(&weights) }),
+                    );
+                    let weights: Vec<String> = weights
+                        .iter()
+                        .map(|(pool, weight)| format!("{}={}", pool, weight))
+                        .collect();
+                    Response::new(Body::from(format!(
+                        "Split of route '{}' set to {}",
+                        route,
+                        weights.join(", ")
+                    )))
+                }
+                Err(e) => admin_error_response(StatusCode::NOT_FOUND, e),
+            };
+
+            return response;
+        }
+    }
+
+    if req.uri().path() == "/admin/backends" {
+        let Some(pool) = ctx.lb.pool(admin_pool) else {
+            return unknown_pool_response(admin_pool);
+        };
+
+        let mut listing = String::new();
+        for backend in pool.backends() {
+            listing.push_str(&format!(
+                "{} state={} weight={} priority={} active={}\n",
+                backend.url,
+                backend.state(),
+                backend.weight,
+                backend.priority,
+                backend.active_connections()
+            ));
+        }
+
+        return Response::new(Body::from(listing));
+    }
+
+    if req.uri().path() == "/admin/backends/add"
+        && let Some(backend) = query_param(&req, "backend")
+    {
+        let protocol = match query_param(&req, "protocol") {
+            None | Some("http1") => BackendProtocol::Http1,
+            Some("http2") => BackendProtocol::Http2,
+            Some("h2c") => BackendProtocol::H2c,
+            Some(other) => {
+                return admin_error_response(
+                    StatusCode::BAD_REQUEST,
+                    format!("Unknown protocol '{}'", other),
+                );
+            }
+        };
+        let backend_config = BackendConfig {
+            url: admin_backend_url(backend),
+            weight: query_param(&req, "weight").and_then(|w| w.parse().ok()),
+            protocol,
+            priority: query_param(&req, "priority")
+                .and_then(|p| p.parse().ok())
+                .unwrap_or(0),
+            zone: query_param(&req, "zone").map(|z| z.to_string()),
+        };
+
+        if ctx.lb.pool(admin_pool).is_none() {
+            return unknown_pool_response(admin_pool);
+        }
+        return match ctx.lb.add_backend(admin_pool, &backend_config) {
+            Ok(()) => {
+                ctx.record(
+                    "backend.add",
+                    Value::Null,
+                    json!({ "pool": admin_pool, "backend": backend_config }),
+                );
+                Response::new(Body::from(format!(
+                    "Added {} to pool '{}'",
+                    backend_config.url, admin_pool
+                )))
+            }
+            Err(e) => admin_error_response(StatusCode::BAD_REQUEST, e),
+        };
+    }
+
+    if req.uri().path() == "/admin/backends/remove"
+        && let Some(backend) = query_param(&req, "backend")
+    {
+        let backend_url = admin_backend_url(backend);
+        let Some(pool) = ctx.lb.pool(admin_pool) else {
+            return unknown_pool_response(admin_pool);
+        };
+        let Some(old) = pool
+            .backend_configs()
+            .into_iter()
+            .find(|b| b.url == backend_url)
+        else {
+            return unknown_backend_response(&backend_url);
+        };
+
+        pool.remove_backend(&backend_url);
+        ctx.record(
+            "backend.remove",
+            json!({ "pool": admin_pool, "backend": old }),
+            Value::Null,
+        );
+
+        return Response::new(Body::from(format!(
+            "Removed {} from pool '{}'",
+            backend_url, admin_pool
+        )));
+    }
+
+    if req.uri().path() == "/admin/backends/drain"
+        && let Some(backend) = query_param(&req, "backend")
+    {
+        let backend_url = admin_backend_url(backend);
+        let Some(pool) = ctx.lb.pool(admin_pool) else {
+            return unknown_pool_response(admin_pool);
+        };
+        let Some(old_state) = pool.find(&backend_url).map(|b| b.state()) else {
+            return unknown_backend_response(&backend_url);
+        };
+
+        // Deploy scripts poll this until the backend reports drained
+        let active = pool.drain_backend(&backend_url).unwrap_or(0);
+        if old_state != "draining" {
+            ctx.record(
+                "backend.drain",
+                json!({ "pool": admin_pool, "backend": backend_url, "state": old_state }),
+                json!({ "pool": admin_pool, "backend": backend_url, "state": "draining" }),
+            );
+        }
+
+        return if active == 0 {
+            Response::new(Body::from(format!("{} drained", backend_url)))
+        } else {
+            Response::new(Body::from(format!(
+                "{} draining ({} active)",
+                backend_url, active
+            )))
+        };
+    }
+
+    if req.uri().path() == "/admin/session-timeout"
+        && let Some(timeout) = query_param(&req, "seconds").and_then(|s| s.parse::<u64>().ok())
+    {
+        let Some(pool) = ctx.lb.pool(admin_pool) else {
+            return unknown_pool_response(admin_pool);
+        };
+        ctx.record(
+            "pool.session_timeout",
+            json!({ "pool": admin_pool, "seconds": pool.sessions.timeout_seconds() }),
+            json!({ "pool": admin_pool, "seconds": timeout }),
+        );
+        pool.set_session_timeout(timeout);
+        return Response::new(Body::from(format!(
+            "Session timeout of pool '{}' set to {} seconds",
+            admin_pool, timeout
+        )));
+    }
+
+    // Everything under /admin belongs to the balancer, so a malformed admin
+    // call is rejected instead of being proxied to a backend
+    admin_error_response(
+        StatusCode::BAD_REQUEST,
+        "Unknown admin endpoint or missing parameters".to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LoadBalancerConfig;
+
+    fn config() -> LoadBalancerConfig {
+        let pool = json!({
+            "strategy": "roundrobin",
+            "backends": [{ "url": "http://127.0.0.1:9101" }]
+        });
+        LoadBalancerConfig {
+            pools: [("stable", &pool), ("canary", &pool)]
+                .into_iter()
+                .map(|(name, pool)| (name.to_string(), serde_json::from_value(pool.clone()).unwrap()))
+                .collect(),
+            routes: vec![
+                serde_json::from_value(json!({
+                    "name": "web",
+                    "split": [{ "pool": "stable", "weight": 90 }, { "pool": "canary", "weight": 10 }]
+                }))
+                .unwrap(),
+            ],
+            ..Default::default()
+        }
+    }
+
+    async fn get(ctx: &AdminContext<'_>, uri: &str) -> (StatusCode, String) {
+        let req = Request::get(uri).body(Body::empty()).unwrap();
+        let response = handle(req, ctx).await;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn split_weights_show_in_config_and_cannot_all_be_zero() {
+        let config = config();
+        let lb = LoadBalancer::new(config.clone()).unwrap();
+        let router = Router::new(&config).unwrap();
+        let audit = AuditLog::open(None).unwrap();
+        let ctx = AdminContext {
+            lb: &lb,
+            router: &router,
+            audit: &audit,
+            who: "test",
+        };
+
+        let (status, _) = get(&ctx, "/admin/split?route=web&pool=stable&weight=0").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = get(&ctx, "/admin/split?route=web&pool=canary&weight=0").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        assert_eq!(
+            router.split_weights("web").unwrap(),
+            [("stable".to_string(), 0), ("canary".to_string(), 10)]
+        );
+
+        let (status, body) = get(&ctx, "/admin/api/v1/config").await;
+        assert_eq!(status, StatusCode::OK);
+        let config: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            config["routes"][0]["split"],
+            json!([{ "pool": "stable", "weight": 0 }, { "pool": "canary", "weight": 10 }])
+        );
+    }
+}
@@ -0,0 +1,835 @@
+use std::sync::Arc;
+
+use hyper::body::Body;
+use hyper::header::{ALLOW, CONTENT_LENGTH, CONTENT_TYPE, HeaderValue, LOCATION};
+use hyper::{Method, Request, Response, StatusCode};
+use log::info;
+use percent_encoding::{NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+
+use crate::config::{
+    BackendConfig, BackendProtocol, BackendUpdate, FilterConfig, LoadBalancerConfig, PoolUpdate,
+    SplitConfig, Strategy,
+};
+use crate::load_balancer::admin::AdminContext;
+use crate::load_balancer::{Backend, LoadBalancer, Pool};
+
+// Lives under /admin so it never shadows a backend's own /api paths
+pub const API_PREFIX: &str = "/admin/api/v1";
+
+// Admin requests are small; anything larger is refused unread
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+// Shown in place of secrets in the config view
+const REDACTED: &str = "***";
+
+struct ApiError {
+    status: StatusCode,
+    message: String,
+    allow: Option<&'static str>,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            message: message.into(),
+            allow: None,
+        }
+    }
+
+    fn bad_request(message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    fn not_found(message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::NOT_FOUND, message)
+    }
+
+    fn method_not_allowed(allow: &'static str) -> Self {
+        ApiError {
+            allow: Some(allow),
+            ..ApiError::new(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed")
+        }
+    }
+
+    fn into_response(self) -> Response<Body> {
+        let mut response = json_response(self.status, &json!({ "error": self.message }));
+        if let Some(allow) = self.allow {
+            response
+                .headers_mut()
+                .insert(ALLOW, HeaderValue::from_static(allow));
+        }
+        response
+    }
+}
+
+type ApiResult = Result<Response<Body>, ApiError>;
+
+#[derive(Serialize)]
+struct BackendStatus {
+    url: String,
+    state: &'static str,
+    weight: u32,
+    protocol: BackendProtocol,
+    priority: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    zone: Option<String>,
+    // Requests and connections currently in flight
+    active: u32,
+    failures: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<f64>,
+}
+
+impl From<&Backend> for BackendStatus {
+    fn from(backend: &Backend) -> Self {
+        BackendStatus {
+            url: backend.url.clone(),
+            state: backend.state(),
+            weight: backend.weight,
+            protocol: backend.protocol,
+            priority: backend.priority,
+            zone: backend.zone.clone(),
+            active: backend.active_connections(),
+            failures: backend.failures(),
+            // Rounded to hundredths of a millisecond
+            latency_ms: backend
+                .latency()
+                .map(|l| (l.as_secs_f64() * 100_000.0).round() / 100.0),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PoolStatus {
+    name: String,
+    strategy: Strategy,
+    session_timeout_seconds: u64,
+    overprovisioning_factor: f64,
+    backends: Vec<BackendStatus>,
+}
+
+impl PoolStatus {
+    fn new(name: &str, pool: &Pool) -> Self {
+        let state = pool.snapshot();
+        PoolStatus {
+            name: name.to_string(),
+            strategy: state.strategy.clone(),
+            session_timeout_seconds: pool.sessions.timeout_seconds(),
+            overprovisioning_factor: state.overprovisioning_factor,
+            backends: state
+                .backends
+                .iter()
+                .map(|b| BackendStatus::from(&**b))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SessionStatus {
+    client: String,
+    backend: String,
+    idle_seconds: u64,
+}
+
+fn json_response<T: Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+    let body = serde_json::to_vec_pretty(value).unwrap_or_default();
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn no_content() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn read_json<T: DeserializeOwned>(req: Request<Body>) -> Result<T, ApiError> {
+    let too_large = || {
+        ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Request body is larger than {} bytes", MAX_BODY_BYTES),
+        )
+    };
+
+    let declared = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if declared.is_some_and(|len| len > MAX_BODY_BYTES) {
+        return Err(too_large());
+    }
+
+    let body = hyper::body::to_bytes(req.into_body())
+        .await
+        .map_err(|e| ApiError::bad_request(format!("Failed to read request body: {}", e)))?;
+    if body.len() > MAX_BODY_BYTES {
+        return Err(too_large());
+    }
+
+    serde_json::from_slice(&body)
+        .map_err(|e| ApiError::bad_request(format!("Invalid JSON body: {}", e)))
+}
+
+// Backends are addressed by their percent-encoded URL, or by host:port
+// for http:// backends
+fn backend_url(segment: &str) -> String {
+    if segment.contains("://") {
+        segment.to_string()
+    } else {
+        format!("http://{}", segment)
+    }
+}
+
+fn backend_location(pool_name: &str, backend_url: &str) -> String {
+    format!(
+        "{}/pools/{}/backends/{}",
+        API_PREFIX,
+        utf8_percent_encode(pool_name, NON_ALPHANUMERIC),
+        utf8_percent_encode(backend_url, NON_ALPHANUMERIC)
+    )
+}
+
+fn find_pool(lb: &LoadBalancer, name: &str) -> Result<Arc<Pool>, ApiError> {
+    lb.pool(name)
+        .ok_or_else(|| ApiError::not_found(format!("Unknown pool '{}'", name)))
+}
+
+// The running config, with split weights as currently set and every token
+// replaced by REDACTED; tokens are still listed by name
+fn config_view(ctx: &AdminContext<'_>) -> LoadBalancerConfig {
+    let mut config = ctx.lb.current_config();
+
+    for route in &mut config.routes {
+        if let Ok(weights) = ctx.router.split_weights(&route.name) {
+            route.split = split_targets(&weights);
+        }
+    }
+
+    for token in config.admin.iter_mut().flat_map(|admin| &mut admin.tokens) {
+        token.token = REDACTED.to_string();
+    }
+    let filter_tokens = config
+        .routes
+        .iter_mut()
+        .flat_map(|route| &mut route.filters)
+        .flat_map(|filter| match filter {
+            FilterConfig::Auth { tokens } => tokens.as_mut_slice(),
+            _ => &mut [],
+        });
+    for token in filter_tokens {
+        token.token = REDACTED.to_string();
+    }
+
+    config
+}
+
+fn find_backend(pool: &Pool, url: &str) -> Result<Arc<Backend>, ApiError> {
+    pool.snapshot()
+        .find(url)
+        .cloned()
+        .ok_or_else(|| ApiError::not_found(format!("Unknown backend {}", url)))
+}
+
+// Versioned JSON admin API. Reads use GET; changes use POST, PUT, PATCH
+// and DELETE with bodies checked against the config types.
+pub async fn handle(req: Request<Body>, ctx: &AdminContext<'_>) -> Response<Body> {
+    dispatch(req, ctx)
+        .await
+        .unwrap_or_else(ApiError::into_response)
+}
+
+async fn dispatch(req: Request<Body>, ctx: &AdminContext<'_>) -> ApiResult {
+    let path = req.uri().path()[API_PREFIX.len()..].trim_matches('/');
+    let segments: Vec<String> = path
+        .split('/')
+        .map(|s| percent_decode_str(s).decode_utf8_lossy().into_owned())
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+    let method = req.method().clone();
+    match segments.as_slice() {
+        ["config"] => match method {
+            Method::GET => Ok(json_response(StatusCode::OK, &config_view(ctx))),
+            _ => Err(ApiError::method_not_allowed("GET")),
+        },
+        ["pools"] => match method {
+            Method::GET => list_pools(ctx),
+            _ => Err(ApiError::method_not_allowed("GET")),
+        },
+        ["pools", pool] => match method {
+            Method::GET => get_pool(ctx, pool),
+            Method::PATCH => update_pool(ctx, pool, read_json(req).await?),
+            _ => Err(ApiError::method_not_allowed("GET, PATCH")),
+        },
+        ["pools", pool, "backends"] => match method {
+            Method::GET => list_backends(ctx, pool),
+            Method::POST => add_backend(ctx, pool, read_json(req).await?),
+            _ => Err(ApiError::method_not_allowed("GET, POST")),
+        },
+        ["pools", pool, "backends", backend] => {
+            let url = backend_url(backend);
+            match method {
+                Method::GET => get_backend(ctx, pool, &url),
+                Method::PATCH => update_backend(ctx, pool, &url, read_json(req).await?),
+                Method::DELETE => remove_backend(ctx, pool, &url),
+                _ => Err(ApiError::method_not_allowed("GET, PATCH, DELETE")),
+            }
+        }
+        ["pools", pool, "backends", backend, "drain"] => match method {
+            Method::POST => drain_backend(ctx, pool, &backend_url(backend)),
+            _ => Err(ApiError::method_not_allowed("POST")),
+        },
+        ["pools", pool, "sessions"] => match method {
+            Method::GET => list_sessions(ctx, pool),
+            Method::DELETE => clear_sessions(ctx, pool),
+            _ => Err(ApiError::method_not_allowed("GET, DELETE")),
+        },
+        ["pools", pool, "sessions", client] => match method {
+            Method::DELETE => remove_session(ctx, pool, client),
+            _ => Err(ApiError::method_not_allowed("DELETE")),
+        },
+        ["routes", route, "split"] => match method {
+            Method::GET => get_split(ctx, route),
+            Method::PUT => set_split(ctx, route, read_json(req).await?),
+            _ => Err(ApiError::method_not_allowed("GET, PUT")),
+        },
+        _ => Err(ApiError::not_found(format!(
+            "Unknown API path '{}'",
+            req.uri().path()
+        ))),
+    }
+}
+
+fn list_pools(ctx: &AdminContext<'_>) -> ApiResult {
+    let mut names = ctx.lb.pool_names();
+    names.sort();
+
+    let pools: Vec<PoolStatus> = names
+        .iter()
+        .filter_map(|name| Some(PoolStatus::new(name, &*ctx.lb.pool(name)?)))
+        .collect();
+
+    Ok(json_response(StatusCode::OK, &pools))
+}
+
+fn get_pool(ctx: &AdminContext<'_>, name: &str) -> ApiResult {
+    let pool = find_pool(ctx.lb, name)?;
+    Ok(json_response(StatusCode::OK, &PoolStatus::new(name, &pool)))
+}
+
+fn pool_settings(pool: &Pool) -> PoolUpdate {
+    let state = pool.snapshot();
+    PoolUpdate {
+        strategy: Some(state.strategy.clone()),
+        session_timeout_seconds: Some(pool.sessions.timeout_seconds()),
+        overprovisioning_factor: Some(state.overprovisioning_factor),
+    }
+}
+
+fn update_pool(ctx: &AdminContext<'_>, name: &str, update: PoolUpdate) -> ApiResult {
+    let pool = find_pool(ctx.lb, name)?;
+    let old = pool_settings(&pool);
+
+    // The only fallible change goes first, so a bad request changes nothing
+    if let Some(factor) = update.overprovisioning_factor {
+        pool.set_overprovisioning_factor(factor)
+            .map_err(ApiError::bad_request)?;
+    }
+    if let Some(strategy) = update.strategy {
+        info!(
+            "Changed load balancing strategy of pool '{}' to {:?}",
+            name, strategy
+        );
+        pool.set_strategy(strategy);
+    }
+    if let Some(timeout) = update.session_timeout_seconds {
+        pool.set_session_timeout(timeout);
+    }
+
+    ctx.record(
+        "pool.update",
+        json!({ "pool": name, "settings": old }),
+        json!({ "pool": name, "settings": pool_settings(&pool) }),
+    );
+
+    Ok(json_response(StatusCode::OK, &PoolStatus::new(name, &pool)))
+}
+
+fn list_backends(ctx: &AdminContext<'_>, pool_name: &str) -> ApiResult {
+    let pool = find_pool(ctx.lb, pool_name)?;
+    let backends: Vec<BackendStatus> = pool
+        .backends()
+        .iter()
+        .map(|b| BackendStatus::from(&**b))
+        .collect();
+    Ok(json_response(StatusCode::OK, &backends))
+}
+
+fn get_backend(ctx: &AdminContext<'_>, pool_name: &str, url: &str) -> ApiResult {
+    let pool = find_pool(ctx.lb, pool_name)?;
+    let backend = find_backend(&pool, url)?;
+    Ok(json_response(
+        StatusCode::OK,
+        &BackendStatus::from(&*backend),
+    ))
+}
+
+fn add_backend(ctx: &AdminContext<'_>, pool_name: &str, backend: BackendConfig) -> ApiResult {
+    let pool = find_pool(ctx.lb, pool_name)?;
+    if pool.find(&backend.url).is_some() {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("Backend {} already exists", backend.url),
+        ));
+    }
+
+    ctx.lb
+        .add_backend(pool_name, &backend)
+        .map_err(ApiError::bad_request)?;
+    ctx.record(
+        "backend.add",
+        Value::Null,
+        json!({ "pool": pool_name, "backend": backend }),
+    );
+
+    let added = find_backend(&pool, &backend.url)?;
+    let mut response = json_response(StatusCode::CREATED, &BackendStatus::from(&*added));
+    if let Ok(location) = HeaderValue::from_str(&backend_location(pool_name, &backend.url)) {
+        response.headers_mut().insert(LOCATION, location);
+    }
+
+    Ok(response)
+}
+
+fn backend_settings(backend: &Backend) -> BackendUpdate {
+    BackendUpdate {
+        weight: Some(backend.weight),
+        priority: Some(backend.priority),
+        zone: backend.zone.clone(),
+    }
+}
+
+fn update_backend(
+    ctx: &AdminContext<'_>,
+    pool_name: &str,
+    url: &str,
+    update: BackendUpdate,
+) -> ApiResult {
+    let pool = find_pool(ctx.lb, pool_name)?;
+    let old = backend_settings(&*find_backend(&pool, url)?);
+
+    if let Some(weight) = update.weight {
+        pool.set_weight(url, weight);
+    }
+    if let Some(priority) = update.priority {
+        pool.set_priority(url, priority);
+    }
+    if let Some(zone) = &update.zone {
+        pool.set_zone(url, zone);
+    }
+
+    let backend = find_backend(&pool, url)?;
+    ctx.record(
+        "backend.update",
+        json!({ "pool": pool_name, "backend": url, "settings": old }),
+        json!({ "pool": pool_name, "backend": url, "settings": backend_settings(&backend) }),
+    );
+
+    Ok(json_response(
+        StatusCode::OK,
+        &BackendStatus::from(&*backend),
+    ))
+}
+
+fn remove_backend(ctx: &AdminContext<'_>, pool_name: &str, url: &str) -> ApiResult {
+    let pool = find_pool(ctx.lb, pool_name)?;
+    let old = pool
+        .backend_configs()
+        .into_iter()
+        .find(|b| b.url == url)
+        .ok_or_else(|| ApiError::not_found(format!("Unknown backend {}", url)))?;
+
+    pool.remove_backend(url);
+    ctx.record(
+        "backend.remove",
+        json!({ "pool": pool_name, "backend": old }),
+        Value::Null,
+    );
+
+    Ok(no_content())
+}
+
+// 200 once the backend is drained and gone, 202 while requests are still
+// in flight; callers repeat the request until they get a 200
+fn drain_backend(ctx: &AdminContext<'_>, pool_name: &str, url: &str) -> ApiResult {
+    let pool = find_pool(ctx.lb, pool_name)?;
+    let old_state = find_backend(&pool, url)?.state();
+    let active = pool.drain_backend(url).unwrap_or(0);
+
+    if old_state != "draining" {
+        ctx.record(
+            "backend.drain",
+            json!({ "pool": pool_name, "backend": url, "state": old_state }),
+            json!({ "pool": pool_name, "backend": url, "state": "draining" }),
+        );
+    }
+
+    let (status, state) = if active == 0 {
+        (StatusCode::OK, "drained")
+    } else {
+        (StatusCode::ACCEPTED, "draining")
+    };
+
+    Ok(json_response(
+        status,
+        &json!({ "url": url, "state": state, "active": active }),
+    ))
+}
+
+fn list_sessions(ctx: &AdminContext<'_>, pool_name: &str) -> ApiResult {
+    let pool = find_pool(ctx.lb, pool_name)?;
+    let mut sessions: Vec<SessionStatus> = pool
+        .sessions
+        .list()
+        .into_iter()
+        .map(|(client, backend, idle)| SessionStatus {
+            client,
+            backend,
+            idle_seconds: idle.as_secs(),
+        })
+        .collect();
+    sessions.sort_by(|a, b| a.client.cmp(&b.client));
+
+    Ok(json_response(StatusCode::OK, &sessions))
+}
+
+fn clear_sessions(ctx: &AdminContext<'_>, pool_name: &str) -> ApiResult {
+    let pool = find_pool(ctx.lb, pool_name)?;
+    let old = pool.sessions.len();
+
+    pool.clear_sessions();
+    ctx.record(
+        "sessions.clear",
+        json!({ "pool": pool_name, "sessions": old }),
+        json!({ "pool": pool_name, "sessions": 0 }),
+    );
+
+    Ok(no_content())
+}
+
+fn remove_session(ctx: &AdminContext<'_>, pool_name: &str, client: &str) -> ApiResult {
+    let pool = find_pool(ctx.lb, pool_name)?;
+    let old = pool
+        .sessions
+        .remove(client)
+        .ok_or_else(|| ApiError::not_found(format!("No session for {}", client)))?;
+
+    ctx.record(
+        "session.remove",
+        json!({ "pool": pool_name, "client": client, "backend": old }),
+        Value::Null,
+    );
+
+    Ok(no_content())
+}
+
+pub fn split_targets(weights: &[(String, u32)]) -> Vec<SplitConfig> {
+    weights
+        .iter()
+        .map(|(pool, weight)| SplitConfig {
+            pool: pool.clone(),
+            weight: *weight,
+        })
+        .collect()
+}
+
+fn get_split(ctx: &AdminContext<'_>, route: &str) -> ApiResult {
+    let weights = ctx
+        .router
+        .split_weights(route)
+        .map_err(ApiError::not_found)?;
+    Ok(json_response(StatusCode::OK, &split_targets(&weights)))
+}
+
+// Takes the new weights of some or all of the split's pools
+fn set_split(ctx: &AdminContext<'_>, route: &str, targets: Vec<SplitConfig>) -> ApiResult {
+    let current = ctx
+        .router
+        .split_weights(route)
+        .map_err(ApiError::not_found)?;
+
+    let mut total = 0;
+    for (pool, weight) in &current {
+        let weight = match targets.iter().find(|t| &t.pool == pool) {
+            Some(target) => target.weight,
+            None => *weight,
+        };
+        total += u64::from(weight);
+    }
+    if let Some(target) = targets
+        .iter()
+        .find(|t| !current.iter().any(|(pool, _)| *pool == t.pool))
+    {
+        return Err(ApiError::bad_request(format!(
+            "Pool '{}' is not part of this split",
+            target.pool
+        )));
+    }
+    if total == 0 {
+        return Err(ApiError::bad_request(
+            "Split needs at least one pool with a weight above 0",
+        ));
+    }
+
+    let mut weights = current.clone();
+    for target in &targets {
+        weights = ctx
+            .router
+            .set_split_weight(route, &target.pool, target.weight)
+            .map_err(ApiError::bad_request)?;
+    }
+    ctx.record(
+        "route.split",
+        json!({ "route": route, "split": split_targets(&current) }),
+        json!({ "route": route, "split": split_targets(&weights) }),
+    );
+
+    Ok(json_response(StatusCode::OK, &split_targets(&weights)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditLog;
+    use crate::router::Router;
+
+    fn config() -> LoadBalancerConfig {
+        let pool = json!({
+            "strategy": "roundrobin",
+            "backends": [{ "url": "http://127.0.0.1:9101" }]
+        });
+        let route = json!({
+            "name": "web",
+            "split": [{ "pool": "stable", "weight": 90 }, { "pool": "canary", "weight": 10 }],
+            "filters": [{ "type": "auth", "tokens": [{ "name": "app", "token": "route-secret" }] }]
+        });
+        let admin = json!({
+            "listen_address": "127.0.0.1:9090",
+            "tokens": [{ "name": "ops", "token": "admin-secret" }]
+        });
+        LoadBalancerConfig {
+            admin: Some(serde_json::from_value(admin).unwrap()),
+            pools: ["stable", "canary"]
+                .into_iter()
+                .map(|name| {
+                    (
+                        name.to_string(),
+                        serde_json::from_value(pool.clone()).unwrap(),
+                    )
+                })
+                .collect(),
+            routes: vec![serde_json::from_value(route).unwrap()],
+            ..Default::default()
+        }
+    }
+
+    struct Admin {
+        lb: LoadBalancer,
+        router: Router,
+        audit: AuditLog,
+    }
+
+    impl Admin {
+        fn new() -> Self {
+            let config = config();
+            Admin {
+                lb: LoadBalancer::new(config.clone()).unwrap(),
+                router: Router::new(&config).unwrap(),
+                audit: AuditLog::open(None).unwrap(),
+            }
+        }
+
+        // Sends a request under API_PREFIX and returns the status, the
+        // response headers and the JSON body (null when empty)
+        async fn call(
+            &self,
+            method: Method,
+            path: &str,
+            body: &str,
+        ) -> (StatusCode, hyper::HeaderMap, Value) {
+            let ctx = AdminContext {
+                lb: &self.lb,
+                router: &self.router,
+                audit: &self.audit,
+                who: "test",
+            };
+            let req = Request::builder()
+                .method(method)
+                .uri(format!("{}{}", API_PREFIX, path))
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let response = handle(req, &ctx).await;
+            let (parts, body) = response.into_parts();
+            let body = hyper::body::to_bytes(body).await.unwrap();
+            let value = if body.is_empty() {
+                Value::Null
+            } else {
+                serde_json::from_slice(&body).unwrap()
+            };
+            (parts.status, parts.headers, value)
+        }
+
+        async fn get(&self, path: &str) -> (StatusCode, Value) {
+            let (status, _, body) = self.call(Method::GET, path, "").await;
+            (status, body)
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_paths_under_the_prefix() {
+        let admin = Admin::new();
+
+        let (status, pools) = admin.get("/pools").await;
+        assert_eq!(status, StatusCode::OK);
+        let names: Vec<&str> = pools
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["canary", "default", "stable"]);
+
+        let (status, pool) = admin.get("/pools/default/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(pool["name"], "default");
+
+        let (status, backend) = admin.get("/pools/default/backends/localhost:9001").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(backend["url"], "http://localhost:9001");
+        let encoded = "/pools/default/backends/http%3A%2F%2Flocalhost%3A9001";
+        assert_eq!(admin.get(encoded).await.0, StatusCode::OK);
+
+        let (status, split) = admin.get("/routes/web/split").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(split[1], json!({ "pool": "canary", "weight": 10 }));
+    }
+
+    #[tokio::test]
+    async fn errors_are_json_with_matching_status() {
+        let admin = Admin::new();
+
+        let (status, body) = admin.get("/nope").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            body,
+            json!({ "error": "Unknown API path '/admin/api/v1/nope'" })
+        );
+
+        let (status, body) = admin.get("/pools/missing").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, json!({ "error": "Unknown pool 'missing'" }));
+
+        let (status, headers, body) = admin.call(Method::DELETE, "/config", "").await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(headers[ALLOW], "GET");
+        assert_eq!(body, json!({ "error": "Method not allowed" }));
+
+        let (status, _, body) = admin.call(Method::PATCH, "/pools/default", "{").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            body["error"]
+                .as_str()
+                .unwrap()
+                .starts_with("Invalid JSON body")
+        );
+
+        let (status, _, body) = admin
+            .call(
+                Method::PATCH,
+                "/pools/default",
+                r#"{"overprovisioning_factor": 0.5}"#,
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].is_string());
+
+        let backend = r#"{"url": "http://127.0.0.1:9102"}"#;
+        let (status, headers, _) = admin
+            .call(Method::POST, "/pools/default/backends", backend)
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(
+            headers[LOCATION],
+            "/admin/api/v1/pools/default/backends/http%3A%2F%2F127%2E0%2E0%2E1%3A9102"
+        );
+        let (status, _, body) = admin
+            .call(Method::POST, "/pools/default/backends", backend)
+            .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(body["error"].is_string());
+
+        let large = " ".repeat(MAX_BODY_BYTES + 1);
+        let (status, _, _) = admin.call(Method::PATCH, "/pools/default", &large).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn config_view_redacts_tokens() {
+        let admin = Admin::new();
+
+        let (status, config) = admin.get("/config").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            config["admin"]["tokens"],
+            json!([{ "name": "ops", "token": REDACTED }])
+        );
+        assert_eq!(
+            config["routes"][0]["filters"][0]["tokens"],
+            json!([{ "name": "app", "token": REDACTED }])
+        );
+
+        let text = config.to_string();
+        assert!(!text.contains("admin-secret"));
+        assert!(!text.contains("route-secret"));
+    }
+
+    #[tokio::test]
+    async fn split_changes_are_validated() {
+        let admin = Admin::new();
+        let put = |body: &'static str| admin.call(Method::PUT, "/routes/web/split", body);
+
+        let (status, _, body) = put(r#"[{"pool": "other", "weight": 5}]"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "Pool 'other' is not part of this split");
+
+        let (status, _, _) =
+            put(r#"[{"pool": "stable", "weight": 0}, {"pool": "canary", "weight": 0}]"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _, _) = admin.call(Method::PUT, "/routes/missing/split", "[]").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Nothing changed so far
+        assert_eq!(
+            admin.router.split_weights("web").unwrap(),
+            [("stable".to_string(), 90), ("canary".to_string(), 10)]
+        );
+
+        let (status, _, body) = put(r#"[{"pool": "canary", "weight": 50}]"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!([{ "pool": "stable", "weight": 90 }, { "pool": "canary", "weight": 50 }])
+        );
+    }
+}
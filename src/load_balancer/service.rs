@@ -1,32 +1,39 @@
 use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use hyper::body::Body;
-use hyper::client::HttpConnector;
-use hyper::header::{HeaderName, HeaderValue};
-use hyper::{Client, Request, Response, StatusCode, Uri};
+use futures::FutureExt;
+use hyper::body::{Body, HttpBody};
+use hyper::header::{HeaderMap, HeaderValue, LOCATION};
+use hyper::{Request, Response, StatusCode, Uri};
 use log::{error, info, warn};
-use tokio::sync::Mutex;
 
+use crate::client::{BackendClients, HttpsClient};
 use crate::config::Strategy;
-use crate::load_balancer::LoadBalancer;
+use crate::filter::{FilterContext, ProxyError};
+use crate::grpc;
+use crate::load_balancer::{Backend, LoadBalancer, Pool};
+use crate::rewrite;
+use crate::router::{Route, RouteAction, SharedRouter};
+use crate::strategy::Outcome;
 
-pub fn clone_headers(src_req: &Request<Body>, dst_req: &mut Request<Body>) {
-    for (name, value) in src_req.headers() {
+pub fn clone_headers(src_headers: &HeaderMap, dst_req: &mut Request<Body>) {
+    for (name, value) in src_headers {
         if name != hyper::header::HOST {
-            dst_req.headers_mut().insert(name.clone(), value.clone());
+            // append keeps repeated headers such as grpc metadata intact
+            dst_req.headers_mut().append(name.clone(), value.clone());
         }
     }
 }
 
 pub fn extract_client_ip(req: &Request<Body>) -> Option<String> {
-    if let Some(forwarded_for) = req.headers().get("X-Forwarded-For") {
-        if let Ok(forwarded_str) = forwarded_for.to_str() {
-            let ips: Vec<&str> = forwarded_str.split(',').collect();
-            if !ips.is_empty() {
-                return Some(ips[0].trim().to_string());
-            }
+    if let Some(forwarded_for) = req.headers().get("X-Forwarded-For")
+        && let Ok(forwarded_str) = forwarded_for.to_str()
+    {
+        let ips: Vec<&str> = forwarded_str.split(',').collect();
+        if !ips.is_empty() {
+            return Some(ips[0].trim().to_string());
         }
     }
 
@@ -37,174 +44,483 @@ pub fn extract_client_ip(req: &Request<Body>) -> Option<String> {
     None
 }
 
+// Sends the request to `uri`, built by joining the request path onto the
+// backend's base path (see `BackendUrl::join`)
 pub async fn forward_request(
-    client: &Client<HttpConnector>,
-    backend: &str,
+    client: &HttpsClient,
+    uri: Uri,
     req: Request<Body>,
 ) -> Result<Response<Body>, hyper::Error> {
-    let uri_string = format!(
-        "{}{}",
-        backend,
-        req.uri().path_and_query().map_or("", |p| p.as_str())
-    );
-    let uri: Uri = uri_string.parse().unwrap();
-
+    // The body is passed through as a stream, so HTTP/2 trailers (e.g. grpc-status)
+    // reach the backend and flow back in the response unchanged.
+    let (parts, body) = req.into_parts();
     let mut new_req = Request::builder()
-        .method(req.method())
+        .method(parts.method)
         .uri(uri)
-        .body(req.into_body())
+        .body(body)
         .unwrap();
 
-    clone_headers(&req, &mut new_req);
+    clone_headers(&parts.headers, &mut new_req);
 
     client.request(new_req).await
 }
 
+// Counts server-side gRPC statuses (e.g. UNAVAILABLE) as backend failures
+// `latency` is the time until the response headers, as for other requests
+fn record_grpc_status(pool: &Pool, backend: &Backend, status: Option<u32>, latency: Duration) {
+    match status {
+        Some(status) if !grpc::is_failure_status(status) => {
+            pool.record_outcome(backend, Outcome::Success { latency });
+            pool.mark_healthy(backend);
+        }
+        Some(status) => {
+            warn!("Backend {} returned grpc-status {}", backend.url, status);
+            pool.record_outcome(backend, Outcome::Failure);
+            pool.mark_unhealthy(backend);
+        }
+        None => {
+            warn!("gRPC call to {} ended without a grpc-status", backend.url);
+            pool.record_outcome(backend, Outcome::Failure);
+            pool.mark_unhealthy(backend);
+        }
+    }
+}
+
+// Streams `body` into a new body, trailers included, and keeps `guard`
+// until the body ends, fails or the client stops reading it
+fn relay_holding<G: Send + 'static>(mut body: Body, guard: G) -> Body {
+    let (mut sender, relayed) = Body::channel();
+
+    // Data that has already arrived goes out together with the headers, so
+    // a small response is still written in one go
+    match body.data().now_or_never() {
+        Some(Some(Ok(data))) => {
+            let _ = sender.try_send_data(data);
+        }
+        Some(Some(Err(e))) => {
+            warn!("Response body failed: {}", e);
+            sender.abort();
+            return relayed;
+        }
+        Some(None) | None => {}
+    }
+    if body.is_end_stream() {
+        return relayed;
+    }
+
+    tokio::spawn(async move {
+        while let Some(chunk) = body.data().await {
+            match chunk {
+                Ok(data) => {
+                    if sender.send_data(data).await.is_err() {
+                        drop(guard);
+                        return;
+                    }
+                }
+                Err(e) => {
+                    warn!("Response body failed: {}", e);
+                    sender.abort();
+                    drop(guard);
+                    return;
+                }
+            }
+        }
+
+        match body.trailers().await {
+            Ok(Some(trailers)) => {
+                let _ = sender.send_trailers(trailers).await;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!("Failed to read response trailers: {}", e);
+                sender.abort();
+            }
+        }
+        drop(guard);
+    });
+
+    relayed
+}
+
 pub async fn handle_request(
     req: Request<Body>,
-    lb: Arc<Mutex<LoadBalancer>>,
-    client: Client<HttpConnector>,
+    lb: Arc<LoadBalancer>,
+    shared_router: Arc<SharedRouter>,
+    clients: BackendClients,
     remote_addr: SocketAddr,
 ) -> Result<Response<Body>, Infallible> {
     info!(
-        "Received request: {} {} from {}",
+        "Received request: {} {} ({:?}) from {}",
         req.method(),
         req.uri(),
+        req.version(),
         remote_addr
     );
 
-    let mut req_with_addr = req;
-    req_with_addr.extensions_mut().insert(remote_addr);
-
-    let client_ip = extract_client_ip(&req_with_addr);
-
-    if req_with_addr.uri().path() == "/admin/strategy" {
-        let query = req_with_addr.uri().query().unwrap_or("");
-        if query.contains("type=weighted") {
-            let mut lb = lb.lock().await;
-            lb.set_strategy(Strategy::WeightedRoundRobin);
-            info!("Changed load balancing strategy to Weighted Round Robin");
-            return Ok(Response::new(Body::from(
-                "Strategy changed to Weighted Round Robin",
-            )));
-        } else if query.contains("type=roundrobin") {
-            let mut lb = lb.lock().await;
-            lb.set_strategy(Strategy::RoundRobin);
-            info!("Changed load balancing strategy to Round Robin");
-            return Ok(Response::new(Body::from("Strategy changed to Round Robin")));
-        } else if query.contains("type=sticky") {
-            let mut lb = lb.lock().await;
-            lb.set_strategy(Strategy::StickySession);
-            info!("Changed load balancing strategy to Sticky Session");
-            return Ok(Response::new(Body::from(
-                "Strategy changed to Sticky Session",
-            )));
+    let mut req = req;
+    req.extensions_mut().insert(remote_addr);
+
+    let router = shared_router.load();
+
+    let Some(route) = router.route(&req) else {
+        warn!("No route matched {} {}", req.method(), req.uri());
+
+        let response = Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("No route matched"))
+            .unwrap();
+
+        return Ok(response);
+    };
+
+    // The route's filters see every request it matches and every response
+    // it sends, whichever step produced it
+    let mut ctx = FilterContext::new(&req, remote_addr, route.name());
+    let early = route.filters.on_request(&mut req, &mut ctx);
+    let mut response = match early.or_else(|| local_response(&route.action, &req)) {
+        Some(response) => response,
+        None => proxy(route, req, &mut ctx, lb, clients).await,
+    };
+    route.filters.on_response(&mut response, &ctx);
+
+    Ok(response)
+}
+
+// Redirects and direct responses never reach a backend
+fn local_response(action: &RouteAction, req: &Request<Body>) -> Option<Response<Body>> {
+    match action {
+        RouteAction::Proxy => None,
+        RouteAction::Redirect { status, location } => {
+            let location = rewrite::expand_request_template(location, req);
+            info!("Redirecting to {} ({})", location, status);
+
+            let response = match HeaderValue::from_str(&location) {
+                Ok(location) => Response::builder()
+                    .status(*status)
+                    .header(LOCATION, location)
+                    .body(Body::empty())
+                    .unwrap(),
+                Err(_) => {
+                    error!("Redirect location '{}' is not a valid header", location);
+                    Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Body::from("Invalid redirect"))
+                        .unwrap()
+                }
+            };
+
+            Some(response)
+        }
+        RouteAction::Direct {
+            status,
+            headers,
+            body,
+        } => {
+            let mut response = Response::new(Body::from(body.clone()));
+            *response.status_mut() = *status;
+            *response.headers_mut() = headers.clone();
+
+            Some(response)
         }
     }
+}
 
-    if req_with_addr.uri().path() == "/admin/weight" {
-        if let Some(query) = req_with_addr.uri().query() {
-            let params: Vec<&str> = query.split('&').collect();
-            let mut backend = None;
-            let mut weight = None;
-
-            for param in params {
-                let kv: Vec<&str> = param.split('=').collect();
-                if kv.len() == 2 {
-                    match kv[0] {
-                        "backend" => backend = Some(kv[1]),
-                        "weight" => weight = kv[1].parse::<u32>().ok(),
-                        _ => {}
-                    }
+// Error response for a request that could not be proxied, after the
+// route's filters have seen the error
+fn proxy_error(
+    route: &Route,
+    ctx: &FilterContext,
+    error: ProxyError,
+    status: StatusCode,
+    body: &'static str,
+) -> Response<Body> {
+    let mut response = Response::builder()
+        .status(status)
+        .body(Body::from(body))
+        .unwrap();
+    route.filters.on_error(&error, &mut response, ctx);
+
+    response
+}
+
+// Picks a backend from the route's pool and forwards the request to it
+async fn proxy(
+    route: &Route,
+    mut req: Request<Body>,
+    ctx: &mut FilterContext,
+    lb: Arc<LoadBalancer>,
+    clients: BackendClients,
+) -> Response<Body> {
+    let client_ip = extract_client_ip(&req);
+    let client_key = client_ip.clone().unwrap_or_else(|| ctx.client_ip.clone());
+    let pool_name = route.select_pool(&req, &client_key).to_string();
+    route.rewrite.apply(&mut req);
+
+    // gRPC clients multiplex every call over one HTTP/2 connection, so each
+    // call is balanced on its own rather than pinned by client address.
+    let is_grpc = grpc::is_grpc_request(&req);
+    let affinity_key = if is_grpc { None } else { client_ip.as_deref() };
+
+    let selected = lb.pool(&pool_name).and_then(|pool| {
+        let backend = pool.get_next_backend(affinity_key)?;
+        // In flight until the response body (or, for gRPC, the call) ends, or
+        // until this future is dropped because the client went away
+        let in_flight = pool.in_flight(&backend);
+        Some((pool, backend, in_flight))
+    });
+
+    let Some((pool, backend, in_flight)) = selected else {
+        error!("No healthy backends available in pool '{}'", pool_name);
+        return proxy_error(
+            route,
+            ctx,
+            ProxyError::NoBackend { pool: pool_name },
+            StatusCode::SERVICE_UNAVAILABLE,
+            "No healthy backends available",
+        );
+    };
+
+    info!(
+        "Forwarding request to backend: {} ({:?}, pool '{}')",
+        backend.url, backend.protocol, pool_name
+    );
+
+    ctx.backend = Some(backend.url.clone());
+    route.filters.on_upstream_request(&mut req, ctx);
+
+    if let Some(mirror) = &route.mirror {
+        req = mirror.tee(req, lb.clone(), clients.clone());
+    }
+
+    let path_and_query = req.uri().path_and_query().map_or("/", |p| p.as_str());
+    let uri = match backend.target.join(path_and_query) {
+        Ok(uri) => uri,
+        Err(e) => {
+            error!("{}", e);
+
+            let error = ProxyError::Upstream {
+                backend: backend.url.clone(),
+                message: e,
+            };
+            return proxy_error(route, ctx, error, StatusCode::BAD_GATEWAY, "Bad Gateway");
+        }
+    };
+
+    let started = Instant::now();
+    match forward_request(clients.get(backend.protocol), uri, req).await {
+        Ok(mut response) => {
+            let elapsed = started.elapsed();
+
+            info!(
+                "Received response from backend {} with status {}",
+                backend.url,
+                response.status()
+            );
+
+            if !is_grpc && pool.strategy() == Strategy::StickySession {
+                let cookie_value = format!("backend={}; Path=/", backend.url);
+                response.headers_mut().insert(
+                    hyper::header::SET_COOKIE,
+                    HeaderValue::from_str(&cookie_value).unwrap(),
+                );
+            }
+
+            // A gRPC call's outcome is only known from its grpc-status
+            if !is_grpc {
+                pool.record_outcome(&backend, Outcome::Success { latency: elapsed });
+                pool.mark_healthy(&backend);
+
+                if response.body().is_end_stream() {
+                    return response;
                 }
+                // Still in flight while the body streams, so draining
+                // waits for it
+                let (parts, body) = response.into_parts();
+                return Response::from_parts(parts, relay_holding(body, in_flight));
             }
 
-            if let (Some(backend), Some(weight)) = (backend, weight) {
-                let mut lb = lb.lock().await;
-                lb.set_weight(&format!("http://{}", backend), weight);
-                return Ok(Response::new(Body::from(format!(
-                    "Weight for {} set to {}",
-                    backend, weight
-                ))));
+            // Trailers-only responses carry grpc-status in the headers
+            if let Some(status) = grpc::grpc_status(response.headers()) {
+                record_grpc_status(&pool, &backend, Some(status), elapsed);
+                return response;
             }
+
+            let (parts, body) = response.into_parts();
+            // A call the client cancels ends without a status; the guard
+            // still closes it, without counting it against the backend
+            let body = grpc::relay_with_status(body, move |status| async move {
+                record_grpc_status(&pool, &backend, status, elapsed);
+                drop(in_flight);
+            });
+
+            Response::from_parts(parts, body)
         }
-    }
-    if req_with_addr.uri().path() == "/admin/session-timeout" {
-        if let Some(query) = req_with_addr.uri().query() {
-            let params: Vec<&str> = query.split('&').collect();
-            for param in params {
-                let kv: Vec<&str> = param.split('=').collect();
-                if kv.len() == 2 && kv[0] == "seconds" {
-                    if let Ok(timeout) = kv[1].parse::<u64>() {
-                        let mut lb = lb.lock().await;
-                        lb.set_session_timeout(timeout);
-                        return Ok(Response::new(Body::from(format!(
-                            "Session timeout set to {} seconds",
-                            timeout
-                        ))));
-                    }
-                }
-            }
+        Err(e) => {
+            error!("Error forwarding request to {}: {}", backend.url, e);
+
+            pool.record_outcome(&backend, Outcome::Failure);
+            pool.mark_unhealthy(&backend);
+
+            let error = ProxyError::Upstream {
+                backend: backend.url.clone(),
+                message: e.to_string(),
+            };
+            proxy_error(
+                route,
+                ctx,
+                error,
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Service Unavailable",
+            )
         }
     }
+}
 
-    let backend = {
-        let mut lb = lb.lock().await;
-        lb.get_next_backend(client_ip.as_deref())
-    };
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
 
-    match backend {
-        Some(backend_url) => {
-            info!("Forwarding request to backend: {}", backend_url);
-
-            match forward_request(&client, &backend_url, req_with_addr).await {
-                Ok(mut response) => {
-                    info!(
-                        "Received response from backend {} with status {}",
-                        backend_url,
-                        response.status()
-                    );
-
-                    if {
-                        let lb = lb.lock().await;
-                        matches!(lb.strategy, Strategy::StickySession)
-                    } {
-                        let cookie_value = format!("backend={}; Path=/", backend_url);
-                        response.headers_mut().insert(
-                            hyper::header::SET_COOKIE,
-                            HeaderValue::from_str(&cookie_value).unwrap(),
-                        );
-                    }
+    use bytes::Bytes;
+    use hyper::service::{make_service_fn, service_fn};
+    use tokio::sync::{mpsc, oneshot};
+    use tokio::time::{sleep, timeout};
 
-                    let mut lb = lb.lock().await;
-                    lb.mark_healthy(&backend_url);
+    use super::*;
+    use crate::config::{BackendConfig, BackendProtocol, LoadBalancerConfig};
+    use crate::load_balancer::DEFAULT_POOL;
+    use crate::router::Router;
+    use crate::strategy::{BalancingStrategy, Selection};
 
-                    Ok(response)
-                }
-                Err(e) => {
-                    error!("Error forwarding request to {}: {}", backend_url, e);
+    // Remembers every outcome it is told about
+    #[derive(Default)]
+    struct Recorder {
+        outcomes: Mutex<Vec<bool>>,
+    }
 
-                    let mut lb = lb.lock().await;
-                    lb.mark_unhealthy(&backend_url);
+    impl BalancingStrategy for Recorder {
+        fn select(&self, selection: &Selection<'_>) -> Option<Arc<Backend>> {
+            selection.backends.first().cloned()
+        }
+
+        fn on_response(&self, _backend: &Backend, outcome: Outcome) {
+            let success = matches!(outcome, Outcome::Success { .. });
+            self.outcomes.lock().unwrap().push(success);
+        }
+    }
 
-                    let response = Response::builder()
-                        .status(StatusCode::SERVICE_UNAVAILABLE)
-                        .body(Body::from("Service Unavailable"))
-                        .unwrap();
+    fn pool() -> (Pool, Arc<Recorder>) {
+        let backends = [BackendConfig {
+            url: "http://localhost:50051".to_string(),
+            weight: None,
+            protocol: BackendProtocol::H2c,
+            priority: 0,
+            zone: None,
+        }];
+        let mut health_check = LoadBalancerConfig::default().health_check;
+        health_check.max_failures = 1;
+        let pool = Pool::from_config(&Strategy::RoundRobin, &backends, health_check, 300).unwrap();
+        let recorder = Arc::new(Recorder::default());
+        pool.set_balancing_strategy(recorder.clone());
+        (pool, recorder)
+    }
 
-                    Ok(response)
-                }
+    #[test]
+    fn grpc_outcome_follows_status() {
+        let (pool, recorder) = pool();
+        let backend = pool.snapshot().backends[0].clone();
+        let latency = Duration::from_millis(5);
+
+        record_grpc_status(&pool, &backend, Some(grpc::STATUS_OK), latency);
+        assert!(backend.is_healthy());
+        // UNAVAILABLE points at the backend
+        record_grpc_status(&pool, &backend, Some(14), latency);
+        assert!(!backend.is_healthy());
+        // NOT_FOUND is the caller's problem
+        record_grpc_status(&pool, &backend, Some(5), latency);
+        assert!(backend.is_healthy());
+        record_grpc_status(&pool, &backend, None, latency);
+
+        assert_eq!(
+            *recorder.outcomes.lock().unwrap(),
+            vec![true, false, true, false]
+        );
+    }
+
+    // Answers each request with headers at once and hands the test the
+    // sender for the body, so the test decides when the body ends
+    async fn slow_backend() -> (SocketAddr, mpsc::UnboundedReceiver<hyper::body::Sender>) {
+        let (senders, received) = mpsc::unbounded_channel();
+        let make_service = make_service_fn(move |_| {
+            let senders = senders.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_req: Request<Body>| {
+                    let (sender, body) = Body::channel();
+                    let _ = senders.send(sender);
+                    async move { Ok::<_, Infallible>(Response::new(body)) }
+                }))
             }
-        }
-        None => {
-            error!("No healthy backends available");
+        });
+        let (addr_tx, addr_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let server = hyper::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+            let _ = addr_tx.send(server.local_addr());
+            let _ = server.await;
+        });
+        (addr_rx.await.unwrap(), received)
+    }
+
+    #[tokio::test]
+    async fn draining_waits_for_a_slow_body() {
+        let (addr, mut senders) = slow_backend().await;
+        let url = format!("http://{}", addr);
+        let config = LoadBalancerConfig {
+            backends: vec![BackendConfig {
+                url: url.clone(),
+                weight: None,
+                protocol: BackendProtocol::Http1,
+                priority: 0,
+                zone: None,
+            }],
+            ..Default::default()
+        };
+        let lb = Arc::new(LoadBalancer::new(config.clone()).unwrap());
+        let router = Arc::new(SharedRouter::new(Router::new(&config).unwrap()));
+        let pool = lb.pool(DEFAULT_POOL).unwrap();
+        let backend = pool.snapshot().backends[0].clone();
+
+        let req = Request::get("/slow").body(Body::empty()).unwrap();
+        let client = "127.0.0.1:40000".parse().unwrap();
+        let response = handle_request(req, lb.clone(), router, BackendClients::new(), client)
+            .await
+            .unwrap();
+        let mut sender = senders.recv().await.unwrap();
+        sender.send_data(Bytes::from("first")).await.unwrap();
 
-            let response = Response::builder()
-                .status(StatusCode::SERVICE_UNAVAILABLE)
-                .body(Body::from("No healthy backends available"))
-                .unwrap();
+        // Headers are in, the body is not: the request is still active
+        assert_eq!(backend.active_connections(), 1);
+        assert_eq!(pool.drain_backend(&url), Some(1));
+        assert_eq!(pool.backends().len(), 1);
 
-            Ok(response)
+        let reading = tokio::spawn(hyper::body::to_bytes(response.into_body()));
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(pool.backends().len(), 1);
+
+        sender.send_data(Bytes::from(" second")).await.unwrap();
+        drop(sender);
+        let body = timeout(Duration::from_secs(2), reading)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(body, "first second");
+
+        for _ in 0..100 {
+            if pool.backends().is_empty() {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
         }
+        assert_eq!(backend.active_connections(), 0);
+        assert!(pool.backends().is_empty());
     }
 }
@@ -1,96 +1,221 @@
-mod config;
-mod health_check;
-mod load_balancer;
-
 use std::convert::Infallible;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 
+use hyper::Server;
+use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Client, Server};
-use log::{error, info};
-use tokio::sync::Mutex;
-
-use crate::config::{LoadBalancerConfig, Strategy};
-use crate::health_check::start_health_checker;
-use crate::load_balancer::LoadBalancer;
-use crate::load_balancer::service::handle_request;
+use log::{error, info, warn};
+
+use load_balancer::LoadBalancer;
+use load_balancer::admin_listener::AdminServer;
+use load_balancer::cli::{self, Cli, Command};
+use load_balancer::client::BackendClients;
+use load_balancer::config::{ListenerMode, LoadBalancerConfig};
+use load_balancer::health_check::start_health_checker;
+use load_balancer::load_balancer::service::handle_request;
+use load_balancer::reload::Reloader;
+use load_balancer::router::{Router, SharedRouter};
+use load_balancer::{http3, tcp_proxy, tls, udp_proxy};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
-
-    let config_path = "config.json";
-    LoadBalancerConfig::generate_default(config_path)?;
-    let config = LoadBalancerConfig::from_file(config_path)?;
-
-    info!("Loaded configuration from {}", config_path);
-
-    let load_balancer = match config.strategy {
-        Strategy::RoundRobin => {
-            let backend_urls: Vec<String> = config.backends.iter().map(|b| b.url.clone()).collect();
+    let cli = Cli::parse().unwrap_or_else(|e| {
+        eprintln!("error: {}\n\nRun with --help for usage", e);
+        std::process::exit(2);
+    });
 
-            Arc::new(Mutex::new(LoadBalancer::new(
-                backend_urls,
-                config.health_check.max_failures,
-                config.clone(),
-            )))
+    match cli.command {
+        Command::Help => {
+            print!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Command::Version => {
+            println!("load-balancer {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Command::PrintDefaultConfig(format) => {
+            print!("{}", LoadBalancerConfig::default().to_string(format)?);
+            return Ok(());
         }
-        Strategy::WeightedRoundRobin | Strategy::StickySession => {
-            let backends_with_weights: Vec<(String, u32)> = config
-                .backends
-                .iter()
-                .map(|b| (b.url.clone(), b.weight.unwrap_or(1)))
-                .collect();
-
-            Arc::new(Mutex::new(LoadBalancer::new_weighted(
-                backends_with_weights,
-                config.health_check.max_failures,
-                config.clone(),
-            )))
+        Command::Run | Command::Check => {}
+    }
+
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Some(level) = cli.log_level {
+        logger.filter_level(level);
+    }
+    logger.init();
+
+    // Without --config, a default config.json is written on first run
+    let config_path = match &cli.config_path {
+        Some(path) => path.clone(),
+        None => {
+            let path = PathBuf::from(cli::DEFAULT_CONFIG_PATH);
+            if cli.command == Command::Run && LoadBalancerConfig::generate_default(&path)? {
+                warn!(
+                    "No config file found, wrote the default configuration to {}",
+                    path.display()
+                );
+            }
+            path
         }
     };
+    let config = cli::load_config(&config_path, &cli.overrides).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    });
 
-    {
-        let mut lb = load_balancer.lock().await;
-        lb.set_strategy(config.strategy.clone());
-        info!(
-            "Initial load balancing strategy set to {:?}",
-            config.strategy
-        );
+    if cli.command == Command::Check {
+        if let Err(e) = LoadBalancer::new(config.clone()) {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+        if let Err(e) = Router::new(&config) {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+        println!("{}: configuration is valid", config_path.display());
+        return Ok(());
     }
 
-    // Create an HTTP client for forwarding requests
-    let client = Client::new();
+    info!("Loaded configuration from {}", config_path.display());
 
-    // Start the health checker
-    start_health_checker(
-        load_balancer.clone(),
-        client.clone(),
-        config.health_check.clone(),
+    let load_balancer = Arc::new(LoadBalancer::new(config.clone())?);
+    info!(
+        "Initial load balancing strategy set to {:?}",
+        config.strategy
     );
 
+    let router = Arc::new(SharedRouter::new(Router::new(&config)?));
+
+    // Create the HTTP clients for forwarding requests (HTTP/1.1, h2 and h2c)
+    let clients = BackendClients::new();
+
+    // Start the health checkers, one per pool
+    for name in load_balancer.pool_names() {
+        start_health_checker(load_balancer.clone(), name, clients.clone());
+    }
+
+    // Reload the config on SIGHUP, and on file changes if enabled
+    Reloader::new(
+        config_path,
+        cli.overrides.clone(),
+        load_balancer.clone(),
+        router.clone(),
+        clients.clone(),
+    )
+    .start(&config.reload)?;
+
+    // Start the admin listener, if the admin endpoints have their own address
+    if let Some(admin_config) = &config.admin {
+        let admin_server = AdminServer::new(admin_config, load_balancer.clone(), router.clone())?;
+        tokio::spawn(async move {
+            if let Err(e) = admin_server.serve().await {
+                error!("Admin listener error: {}", e);
+            }
+        });
+    } else if config.mode == ListenerMode::Http {
+        info!("Admin endpoints are disabled; set `admin` to serve them on their own address");
+    }
+
+    // Start the HTTPS listener, which negotiates HTTP/2 via ALPN, and the
+    // optional HTTP/3 listener that shares its certificate
+    if let Some(tls_config) = &config.tls {
+        let tls_addr: SocketAddr = tls_config.listen_address.parse()?;
+        let server_config = tls::load_server_config(tls_config)?;
+
+        let mut alt_svc = None;
+        if let Some(h3_addr) = &tls_config.http3_listen_address {
+            let h3_addr: SocketAddr = h3_addr.parse()?;
+            alt_svc = Some(http3::alt_svc_value(&h3_addr));
+
+            let server_config = server_config.clone();
+            let lb_ref = load_balancer.clone();
+            let router_ref = router.clone();
+            let clients_ref = clients.clone();
+            tokio::spawn(async move {
+                if let Err(e) =
+                    http3::serve_http3(h3_addr, server_config, lb_ref, router_ref, clients_ref)
+                        .await
+                {
+                    error!("HTTP/3 listener error: {}", e);
+                }
+            });
+        }
+
+        let lb_ref = load_balancer.clone();
+        let router_ref = router.clone();
+        let clients_ref = clients.clone();
+        tokio::spawn(async move {
+            if let Err(e) = tls::serve_https(
+                tls_addr,
+                server_config,
+                lb_ref,
+                router_ref,
+                clients_ref,
+                alt_svc,
+            )
+            .await
+            {
+                error!("HTTPS listener error: {}", e);
+            }
+        });
+    }
+
     // Parse the address to listen on
     let addr: SocketAddr = config.listen_address.parse()?;
 
+    match config.mode {
+        ListenerMode::Tcp => {
+            if let Err(e) = tcp_proxy::serve_tcp(addr, load_balancer, config.tcp.clone()).await {
+                error!("TCP proxy error: {}", e);
+            }
+
+            info!("Load balancer stopped");
+            return Ok(());
+        }
+        ListenerMode::Udp => {
+            if let Err(e) = udp_proxy::serve_udp(addr, load_balancer, config.udp.clone()).await {
+                error!("UDP proxy error: {}", e);
+            }
+
+            info!("Load balancer stopped");
+            return Ok(());
+        }
+        ListenerMode::Http => {}
+    }
+
     info!("Starting load balancer on {}", addr);
 
     // Create the service that will handle incoming requests
     let lb_ref = load_balancer.clone();
-    let make_service = make_service_fn(move |conn| {
+    let router_ref = router.clone();
+    let make_service = make_service_fn(move |conn: &AddrStream| {
         let lb_clone = lb_ref.clone();
-        let client_clone = client.clone();
+        let router_clone = router_ref.clone();
+        let clients_clone = clients.clone();
         let remote_addr = conn.remote_addr();
 
         async move {
             Ok::<_, Infallible>(service_fn(move |req| {
-                handle_request(req, lb_clone.clone(), client_clone.clone(), remote_addr)
+                handle_request(
+                    req,
+                    lb_clone.clone(),
+                    router_clone.clone(),
+                    clients_clone.clone(),
+                    remote_addr,
+                )
             }))
         }
     });
 
-    // Create and start the server
-    let server = Server::bind(&addr).serve(make_service);
+    // Create and start the server. HTTP/1.1 and prior-knowledge h2c are both
+    // accepted on the plain listener; the connection preface picks the protocol.
+    // Responses are often written in pieces as they stream from the backend,
+    // so Nagle's algorithm is turned off, as on the TCP proxy.
+    let server = Server::bind(&addr).tcp_nodelay(true).serve(make_service);
 
     // Run the server
     if let Err(e) = server.await {
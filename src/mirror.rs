@@ -0,0 +1,373 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use hyper::body::{Body, HttpBody};
+use hyper::{Request, Response};
+use log::{debug, info, warn};
+use tokio::sync::{Semaphore, mpsc};
+
+use crate::client::BackendClients;
+use crate::config::MirrorConfig;
+use crate::load_balancer::LoadBalancer;
+use crate::load_balancer::service::forward_request;
+
+// Chunks buffered for the shadow request before it is given up on
+const MIRROR_BUFFER_CHUNKS: usize = 64;
+
+pub struct Mirror {
+    pool: String,
+    percentage: f64,
+    // One permit per mirrored request in flight
+    slots: Arc<Semaphore>,
+    timeout: Duration,
+}
+
+impl Mirror {
+    pub fn compile(config: &MirrorConfig) -> Result<Self, String> {
+        if !(0.0..=100.0).contains(&config.percentage) {
+            return Err(format!(
+                "Mirror percentage {} is not between 0 and 100",
+                config.percentage
+            ));
+        }
+
+        if config.max_in_flight == 0 {
+            return Err("Mirror max_in_flight must be at least 1".to_string());
+        }
+        if config.timeout_seconds == 0 {
+            return Err("Mirror timeout_seconds must be at least 1".to_string());
+        }
+
+        Ok(Mirror {
+            pool: config.pool.clone(),
+            percentage: config.percentage,
+            slots: Arc::new(Semaphore::new(config.max_in_flight)),
+            timeout: Duration::from_secs(config.timeout_seconds),
+        })
+    }
+
+    fn sample(&self) -> bool {
+        fastrand::f64() * 100.0 < self.percentage
+    }
+
+    // Sends a copy of a sampled share of requests to the shadow pool. The
+    // returned request goes to the primary backend as before; the shadow
+    // request runs in its own task and its response is discarded. While
+    // max_in_flight copies are still running, no new ones are made, so a
+    // hung shadow pool cannot pile up tasks and connections.
+    pub fn tee(
+        &self,
+        req: Request<Body>,
+        lb: Arc<LoadBalancer>,
+        clients: BackendClients,
+    ) -> Request<Body> {
+        if !self.sample() {
+            return req;
+        }
+        let Ok(slot) = self.slots.clone().try_acquire_owned() else {
+            debug!("Mirror pool '{}' is at max_in_flight, skipping", self.pool);
+            return req;
+        };
+
+        let (parts, body) = req.into_parts();
+        let (primary_body, shadow_body) = if body.is_end_stream() {
+            (body, Body::empty())
+        } else {
+            tee_body(body)
+        };
+
+        let mut shadow = Request::new(shadow_body);
+        *shadow.method_mut() = parts.method.clone();
+        *shadow.uri_mut() = parts.uri.clone();
+        *shadow.headers_mut() = parts.headers.clone();
+
+        let pool = self.pool.clone();
+        let timeout = self.timeout;
+        tokio::spawn(async move {
+            if tokio::time::timeout(timeout, send_mirror(shadow, &pool, lb, clients))
+                .await
+                .is_err()
+            {
+                warn!("Mirrored request to pool '{}' timed out", pool);
+            }
+            drop(slot);
+        });
+
+        Request::from_parts(parts, primary_body)
+    }
+}
+
+// Splits a body in two. The primary copy keeps backpressure and trailers;
+// the shadow copy is cut off with an error if it falls behind, so a slow
+// shadow backend never holds up the primary one.
+fn tee_body(mut body: Body) -> (Body, Body) {
+    let (mut sender, primary) = Body::channel();
+    let (shadow_tx, mut shadow_rx) = mpsc::channel::<io::Result<Bytes>>(MIRROR_BUFFER_CHUNKS);
+    let shadow = Body::wrap_stream(futures::stream::poll_fn(move |cx| shadow_rx.poll_recv(cx)));
+
+    tokio::spawn(async move {
+        let mut shadow_tx = Some(shadow_tx);
+
+        while let Some(chunk) = body.data().await {
+            match chunk {
+                Ok(data) => {
+                    if let Some(tx) = &shadow_tx {
+                        // Keep the last slot free for the error that cancels
+                        // the shadow body
+                        let sent = if tx.capacity() > 1 {
+                            tx.try_send(Ok(data.clone())).is_ok()
+                        } else {
+                            warn!("Mirrored request fell behind, cancelling it");
+                            let _ = tx.try_send(Err(io::Error::other("mirror fell behind")));
+                            false
+                        };
+                        if !sent {
+                            shadow_tx = None;
+                        }
+                    }
+
+                    if sender.send_data(data).await.is_err() {
+                        cancel_shadow(shadow_tx);
+                        return;
+                    }
+                }
+                Err(e) => {
+                    warn!("Request body failed: {}", e);
+                    sender.abort();
+                    cancel_shadow(shadow_tx);
+                    return;
+                }
+            }
+        }
+
+        match body.trailers().await {
+            Ok(Some(trailers)) => {
+                let _ = sender.send_trailers(trailers).await;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!("Failed to read request trailers: {}", e);
+                sender.abort();
+                cancel_shadow(shadow_tx);
+            }
+        }
+    });
+
+    (primary, shadow)
+}
+
+fn cancel_shadow(shadow_tx: Option<mpsc::Sender<io::Result<Bytes>>>) {
+    if let Some(tx) = shadow_tx {
+        let _ = tx.try_send(Err(io::Error::other("request body failed")));
+    }
+}
+
+async fn send_mirror(
+    req: Request<Body>,
+    pool_name: &str,
+    lb: Arc<LoadBalancer>,
+    clients: BackendClients,
+) {
+    let backend = lb
+        .pool(pool_name)
+        .and_then(|pool| pool.get_next_backend(None));
+
+    let Some(backend) = backend else {
+        warn!("No healthy backends in mirror pool '{}'", pool_name);
+        return;
+    };
+
+    let path_and_query = req.uri().path_and_query().map_or("/", |p| p.as_str());
+    let uri = match backend.target.join(path_and_query) {
+        Ok(uri) => uri,
+        Err(e) => {
+            warn!("{}", e);
+            return;
+        }
+    };
+
+    match forward_request(clients.get(backend.protocol), uri, req).await {
+        Ok(response) => {
+            let status = response.status();
+            discard(response).await;
+            info!(
+                "Mirrored request to {} (pool '{}') returned {}",
+                backend.url, pool_name, status
+            );
+        }
+        // Not counted against the backend: the shadow body may have been
+        // cut off on purpose, and the pool's health checker covers outages
+        Err(e) => warn!("Mirrored request to {} failed: {}", backend.url, e),
+    }
+}
+
+// Reads the body to the end so the connection can be reused
+async fn discard(response: Response<Body>) {
+    let mut body = response.into_body();
+    while let Some(chunk) = body.data().await {
+        if chunk.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+    use tokio::time::{sleep, timeout};
+
+    use super::*;
+    use crate::config::{BackendConfig, BackendProtocol, LoadBalancerConfig};
+    use crate::load_balancer::DEFAULT_POOL;
+
+    const CHUNK: usize = 64 * 1024;
+
+    // Accepts connections and never answers. With `drain` set it reads each
+    // connection to the end and counts it as closed; otherwise it never
+    // reads, so anything sent to it backs up.
+    struct StalledBackend {
+        addr: SocketAddr,
+        accepted: Arc<AtomicUsize>,
+        closed: Arc<AtomicUsize>,
+    }
+
+    async fn stalled_backend(drain: bool) -> StalledBackend {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backend = StalledBackend {
+            addr: listener.local_addr().unwrap(),
+            accepted: Arc::new(AtomicUsize::new(0)),
+            closed: Arc::new(AtomicUsize::new(0)),
+        };
+        let (accepted, closed) = (backend.accepted.clone(), backend.closed.clone());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let closed = closed.clone();
+                tokio::spawn(async move {
+                    if drain {
+                        let _ = stream.read_to_end(&mut Vec::new()).await;
+                        closed.fetch_add(1, Ordering::SeqCst);
+                    } else {
+                        sleep(Duration::from_secs(60)).await;
+                    }
+                });
+            }
+        });
+        backend
+    }
+
+    fn load_balancer(addr: SocketAddr) -> Arc<LoadBalancer> {
+        let config = LoadBalancerConfig {
+            backends: vec![BackendConfig {
+                url: format!("http://{}", addr),
+                weight: None,
+                protocol: BackendProtocol::Http1,
+                priority: 0,
+                zone: None,
+            }],
+            ..Default::default()
+        };
+        Arc::new(LoadBalancer::new(config).unwrap())
+    }
+
+    fn mirror(max_in_flight: usize, timeout_seconds: u64) -> Mirror {
+        Mirror::compile(&MirrorConfig {
+            pool: DEFAULT_POOL.to_string(),
+            percentage: 100.0,
+            max_in_flight,
+            timeout_seconds,
+        })
+        .unwrap()
+    }
+
+    fn get() -> Request<Body> {
+        Request::builder()
+            .uri("/mirrored")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn wait_for(condition: impl Fn() -> bool) {
+        for _ in 0..300 {
+            if condition() {
+                return;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition not met within three seconds");
+    }
+
+    #[tokio::test]
+    async fn slow_mirror_does_not_hold_up_the_primary() {
+        let backend = stalled_backend(false).await;
+        let mirror = mirror(1, 30);
+        let (mut sender, body) = Body::channel();
+        let req = Request::builder()
+            .method("POST")
+            .uri("/upload")
+            .body(body)
+            .unwrap();
+
+        let primary = mirror.tee(req, load_balancer(backend.addr), BackendClients::new());
+        // Far more than the mirror buffer and the socket buffers together
+        let chunks = 4 * MIRROR_BUFFER_CHUNKS;
+        tokio::spawn(async move {
+            for _ in 0..chunks {
+                if sender.send_data(Bytes::from(vec![0; CHUNK])).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        let received = timeout(Duration::from_secs(5), async {
+            let mut body = primary.into_body();
+            let mut received = 0;
+            while let Some(chunk) = body.data().await {
+                received += chunk.unwrap().len();
+            }
+            received
+        })
+        .await
+        .expect("primary body was held up by the mirror");
+        assert_eq!(received, chunks * CHUNK);
+    }
+
+    #[tokio::test]
+    async fn mirrors_beyond_max_in_flight_are_dropped() {
+        let backend = stalled_backend(false).await;
+        let lb = load_balancer(backend.addr);
+        let clients = BackendClients::new();
+        let mirror = mirror(1, 30);
+
+        mirror.tee(get(), lb.clone(), clients.clone());
+        wait_for(|| backend.accepted.load(Ordering::SeqCst) == 1).await;
+        assert_eq!(mirror.slots.available_permits(), 0);
+
+        for _ in 0..3 {
+            mirror.tee(get(), lb.clone(), clients.clone());
+        }
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(backend.accepted.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn timeout_cancels_the_mirrored_request() {
+        let backend = stalled_backend(true).await;
+        let mirror = mirror(1, 1);
+
+        mirror.tee(get(), load_balancer(backend.addr), BackendClients::new());
+        wait_for(|| backend.accepted.load(Ordering::SeqCst) == 1).await;
+        assert_eq!(mirror.slots.available_permits(), 0);
+
+        // The connection is closed and the slot handed back once the
+        // timeout drops the request
+        wait_for(|| backend.closed.load(Ordering::SeqCst) == 1).await;
+        wait_for(|| mirror.slots.available_permits() == 1).await;
+    }
+}
@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use log::{error, info};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::time::sleep;
+
+use crate::cli::{self, Overrides};
+use crate::client::BackendClients;
+use crate::config::ReloadConfig;
+use crate::health_check::start_health_checker;
+use crate::load_balancer::LoadBalancer;
+use crate::router::{Router, SharedRouter};
+
+// Re-reads the config file and applies it to the running balancer. Pools
+// and backends that are still configured keep their health state and
+// sticky sessions; routes are swapped in one step. Command-line and
+// environment overrides are applied again. A config that fails to
+// parse or validate is rejected and the running one stays in place.
+#[derive(Clone)]
+pub struct Reloader {
+    path: PathBuf,
+    overrides: Overrides,
+    lb: Arc<LoadBalancer>,
+    router: Arc<SharedRouter>,
+    clients: BackendClients,
+}
+
+impl Reloader {
+    pub fn new(
+        path: impl Into<PathBuf>,
+        overrides: Overrides,
+        lb: Arc<LoadBalancer>,
+        router: Arc<SharedRouter>,
+        clients: BackendClients,
+    ) -> Self {
+        Reloader {
+            path: path.into(),
+            overrides,
+            lb,
+            router,
+            clients,
+        }
+    }
+
+    // Reloads on SIGHUP and, if configured, whenever the file changes
+    pub fn start(self, config: &ReloadConfig) -> std::io::Result<()> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        let reloader = self.clone();
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                info!("Received SIGHUP, reloading {}", reloader.path.display());
+                reloader.reload();
+            }
+        });
+
+        if config.watch {
+            let interval = Duration::from_secs(config.poll_interval_seconds.max(1));
+            tokio::spawn(self.watch(interval));
+        }
+
+        Ok(())
+    }
+
+    async fn watch(self, interval: Duration) {
+        let mut last_seen = self.file_stamp();
+        loop {
+            sleep(interval).await;
+
+            let stamp = self.file_stamp();
+            if stamp.is_some() && stamp != last_seen {
+                last_seen = stamp;
+                info!("{} changed, reloading", self.path.display());
+                self.reload();
+            }
+        }
+    }
+
+    // Modification time and size, enough to notice an edit or replacement
+    fn file_stamp(&self) -> Option<(SystemTime, u64)> {
+        let metadata = fs::metadata(&self.path).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+
+    pub fn reload(&self) {
+        if let Err(e) = self.try_reload() {
+            error!("Rejected new configuration, keeping the running one: {}", e);
+        }
+    }
+
+    fn try_reload(&self) -> Result<(), String> {
+        let config = cli::load_config(&self.path, &self.overrides)?;
+        let router = Router::new(&config).map_err(|e| e.to_string())?;
+        router.keep_split_weights(&self.router.load());
+
+        let added_pools = self.lb.reload(config)?;
+        // Swapped after the pools so requests never see routes to a pool
+        // that does not exist yet
+        self.router.store(router);
+
+        for name in added_pools {
+            start_health_checker(self.lb.clone(), name, self.clients.clone());
+        }
+
+        info!("Reloaded configuration from {}", self.path.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,347 @@
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::{Request, Uri};
+use log::warn;
+use regex::Regex;
+use uuid::Uuid;
+
+use crate::config::{HeaderRewriteConfig, PathRewriteConfig};
+use crate::router::{request_host, strip_path_prefix};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Values available to header templates
+pub struct Variables<'a> {
+    pub client_ip: &'a str,
+    pub request_id: &'a str,
+    pub backend: &'a str,
+}
+
+impl Variables<'_> {
+    fn expand(&self, template: &str) -> String {
+        expand(
+            template,
+            &[
+                ("client_ip", self.client_ip),
+                ("request_id", self.request_id),
+                ("backend", self.backend),
+            ],
+        )
+    }
+}
+
+// Expands $host, $path, $query and $request_uri from the incoming request,
+// e.g. "https://$host$request_uri" for HTTP to HTTPS redirects
+pub fn expand_request_template<B>(template: &str, req: &Request<B>) -> String {
+    let uri = req.uri();
+    let host = request_host(req).unwrap_or_default();
+    expand(
+        template,
+        &[
+            (
+                "request_uri",
+                uri.path_and_query().map_or("/", |p| p.as_str()),
+            ),
+            ("host", &host),
+            ("path", uri.path()),
+            ("query", uri.query().unwrap_or("")),
+        ],
+    )
+}
+
+// Replaces each $name in one left-to-right pass, preferring the longest
+// name. Substituted values are never scanned again, so a client-supplied
+// value containing "$backend" stays as it is. Unknown names are kept.
+fn expand(template: &str, variables: &[(&str, &str)]) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(i) = rest.find('$') {
+        expanded.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+        let variable = variables
+            .iter()
+            .filter(|(name, _)| after.starts_with(name))
+            .max_by_key(|(name, _)| name.len());
+        match variable {
+            Some((name, value)) => {
+                expanded.push_str(value);
+                rest = &after[name.len()..];
+            }
+            None => {
+                expanded.push('$');
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+// Reuses the caller's X-Request-Id so a request can be traced end to end
+pub fn request_id<B>(req: &Request<B>) -> String {
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
+#[derive(Default)]
+pub struct PathRewrite {
+    strip_prefix: Option<String>,
+    regex: Option<(Regex, String)>,
+    add_prefix: Option<String>,
+}
+
+impl PathRewrite {
+    pub fn compile(config: &PathRewriteConfig) -> Result<Self, String> {
+        let regex = match &config.regex {
+            Some(rewrite) => Some((
+                Regex::new(&rewrite.pattern)
+                    .map_err(|e| format!("Invalid rewrite pattern '{}': {}", rewrite.pattern, e))?,
+                rewrite.replacement.clone(),
+            )),
+            None => None,
+        };
+
+        Ok(PathRewrite {
+            strip_prefix: config.strip_prefix.clone(),
+            regex,
+            add_prefix: config.add_prefix.clone(),
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.strip_prefix.is_none() && self.regex.is_none() && self.add_prefix.is_none()
+    }
+
+    fn rewrite(&self, path: &str) -> String {
+        let mut path = path.to_string();
+
+        if let Some(prefix) = &self.strip_prefix
+            && let Some(rest) = strip_path_prefix(&path, prefix)
+        {
+            path = if rest.starts_with('/') {
+                rest.to_string()
+            } else {
+                format!("/{}", rest)
+            };
+        }
+
+        if let Some((regex, replacement)) = &self.regex {
+            path = regex.replace(&path, replacement.as_str()).into_owned();
+        }
+
+        if let Some(prefix) = &self.add_prefix {
+            path = format!("{}{}", prefix.trim_end_matches('/'), path);
+        }
+
+        path
+    }
+
+    // Rewrites the path of the request URI, keeping the query string
+    pub fn apply<B>(&self, req: &mut Request<B>) {
+        if self.is_empty() {
+            return;
+        }
+
+        let path = self.rewrite(req.uri().path());
+        let path_and_query = match req.uri().query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path,
+        };
+
+        let mut parts = req.uri().clone().into_parts();
+        match path_and_query.parse() {
+            Ok(path_and_query) => {
+                parts.path_and_query = Some(path_and_query);
+                if let Ok(uri) = Uri::from_parts(parts) {
+                    *req.uri_mut() = uri;
+                }
+            }
+            Err(e) => warn!("Rewritten path '{}' is invalid: {}", path_and_query, e),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HeaderRewrite {
+    remove: Vec<HeaderName>,
+    set: Vec<(HeaderName, String)>,
+    add: Vec<(HeaderName, String)>,
+}
+
+impl HeaderRewrite {
+    pub fn compile(config: &HeaderRewriteConfig) -> Result<Self, String> {
+        let parse_name = |name: &str| {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("Invalid header name '{}'", name))
+        };
+        let parse_values = |values: &std::collections::HashMap<String, String>| {
+            values
+                .iter()
+                .map(|(name, value)| Ok((parse_name(name)?, value.clone())))
+                .collect::<Result<Vec<_>, String>>()
+        };
+
+        Ok(HeaderRewrite {
+            remove: config
+                .remove
+                .iter()
+                .map(|name| parse_name(name))
+                .collect::<Result<_, _>>()?,
+            set: parse_values(&config.set)?,
+            add: parse_values(&config.add)?,
+        })
+    }
+
+    pub fn apply(&self, headers: &mut HeaderMap, vars: &Variables) {
+        for name in &self.remove {
+            headers.remove(name);
+        }
+
+        for (name, template) in &self.set {
+            match HeaderValue::from_str(&vars.expand(template)) {
+                Ok(value) => {
+                    headers.insert(name.clone(), value);
+                }
+                Err(_) => warn!("Header {} expanded to an invalid value", name),
+            }
+        }
+
+        for (name, template) in &self.add {
+            match HeaderValue::from_str(&vars.expand(template)) {
+                Ok(value) => {
+                    headers.append(name.clone(), value);
+                }
+                Err(_) => warn!("Header {} expanded to an invalid value", name),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RegexRewriteConfig;
+
+    fn rewrite(
+        strip_prefix: Option<&str>,
+        regex: Option<(&str, &str)>,
+        add_prefix: Option<&str>,
+    ) -> PathRewrite {
+        PathRewrite::compile(&PathRewriteConfig {
+            strip_prefix: strip_prefix.map(str::to_string),
+            regex: regex.map(|(pattern, replacement)| RegexRewriteConfig {
+                pattern: pattern.to_string(),
+                replacement: replacement.to_string(),
+            }),
+            add_prefix: add_prefix.map(str::to_string),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn strips_whole_segments() {
+        let strip = rewrite(Some("/billing"), None, None);
+        assert_eq!(strip.rewrite("/billing/invoices"), "/invoices");
+        assert_eq!(strip.rewrite("/billing"), "/");
+        assert_eq!(strip.rewrite("/billingx"), "/billingx");
+        assert_eq!(strip.rewrite("/other"), "/other");
+
+        let strip = rewrite(Some("/billing/"), None, None);
+        assert_eq!(strip.rewrite("/billing/invoices"), "/invoices");
+        assert_eq!(strip.rewrite("/billing"), "/billing");
+    }
+
+    #[test]
+    fn replaces_with_captures() {
+        let regex = rewrite(None, Some(("^/v1/(?P<rest>.*)$", "/v2/${rest}")), None);
+        assert_eq!(regex.rewrite("/v1/users"), "/v2/users");
+        assert_eq!(regex.rewrite("/v3/users"), "/v3/users");
+    }
+
+    #[test]
+    fn adds_prefix_once() {
+        assert_eq!(
+            rewrite(None, None, Some("/internal")).rewrite("/a"),
+            "/internal/a"
+        );
+        assert_eq!(
+            rewrite(None, None, Some("/internal/")).rewrite("/a"),
+            "/internal/a"
+        );
+    }
+
+    #[test]
+    fn applies_steps_in_order() {
+        let all = rewrite(
+            Some("/billing"),
+            Some(("^/v1/(.*)$", "/v2/$1")),
+            Some("/internal"),
+        );
+        assert_eq!(all.rewrite("/billing/v1/invoices"), "/internal/v2/invoices");
+    }
+
+    #[test]
+    fn apply_keeps_query() {
+        let strip = rewrite(Some("/billing"), None, None);
+        let mut req = Request::builder()
+            .uri("http://lb/billing/invoices?page=2")
+            .body(())
+            .unwrap();
+        strip.apply(&mut req);
+        assert_eq!(req.uri().to_string(), "http://lb/invoices?page=2");
+    }
+
+    #[test]
+    fn expands_header_variables() {
+        let variables = Variables {
+            client_ip: "10.0.0.9",
+            request_id: "abc",
+            backend: "http://10.0.0.5:8080",
+        };
+        assert_eq!(
+            variables.expand("$client_ip via $backend ($request_id) costs $5"),
+            "10.0.0.9 via http://10.0.0.5:8080 (abc) costs $5"
+        );
+    }
+
+    #[test]
+    fn hostile_request_id_is_not_expanded_again() {
+        let variables = Variables {
+            client_ip: "10.0.0.9",
+            request_id: "$backend $client_ip",
+            backend: "http://10.0.0.5:8080",
+        };
+        assert_eq!(variables.expand("$request_id"), "$backend $client_ip");
+    }
+
+    #[test]
+    fn request_template_takes_values_literally() {
+        let req = Request::builder()
+            .uri("/a$host/$query?q=$path")
+            .header("host", "example.com")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            expand_request_template("https://$host$request_uri", &req),
+            "https://example.com/a$host/$query?q=$path"
+        );
+        assert_eq!(
+            expand_request_template("$path|$query", &req),
+            "/a$host/$query|q=$path"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_pattern() {
+        let config = PathRewriteConfig {
+            regex: Some(RegexRewriteConfig {
+                pattern: "(".to_string(),
+                replacement: String::new(),
+            }),
+            ..Default::default()
+        };
+        assert!(PathRewrite::compile(&config).is_err());
+    }
+}
@@ -0,0 +1,572 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use hyper::header::{HOST, HeaderMap, HeaderName, HeaderValue};
+use hyper::http::uri::Authority;
+use hyper::{Request, StatusCode};
+use log::info;
+use regex::Regex;
+
+use crate::config::{LoadBalancerConfig, RouteConfig};
+use crate::filter::FilterChain;
+use crate::load_balancer::DEFAULT_POOL;
+use crate::mirror::Mirror;
+use crate::rewrite::PathRewrite;
+use crate::split::Split;
+
+enum HostMatch {
+    Exact(String),
+    // "*.example.com" is stored as ".example.com"
+    Suffix(String),
+    Any,
+}
+
+impl HostMatch {
+    fn parse(pattern: &str) -> Self {
+        let pattern = pattern.to_ascii_lowercase();
+        if pattern == "*" {
+            HostMatch::Any
+        } else if let Some(suffix) = pattern.strip_prefix('*') {
+            HostMatch::Suffix(suffix.to_string())
+        } else {
+            HostMatch::Exact(pattern)
+        }
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            HostMatch::Exact(expected) => host == expected,
+            HostMatch::Suffix(suffix) => host.len() > suffix.len() && host.ends_with(suffix),
+            HostMatch::Any => true,
+        }
+    }
+}
+
+// What a matched route does with the request
+pub enum RouteAction {
+    Proxy,
+    Redirect {
+        status: StatusCode,
+        location: String,
+    },
+    Direct {
+        status: StatusCode,
+        headers: HeaderMap,
+        body: String,
+    },
+}
+
+pub struct Route {
+    name: String,
+    host: Option<HostMatch>,
+    path: Option<String>,
+    path_prefix: Option<String>,
+    path_regex: Option<Regex>,
+    methods: Vec<String>,
+    headers: Vec<(String, String)>,
+    query: Vec<(String, String)>,
+    pool: String,
+    split: Option<Split>,
+    pub action: RouteAction,
+    pub mirror: Option<Mirror>,
+    pub rewrite: PathRewrite,
+    pub filters: FilterChain,
+}
+
+impl Route {
+    fn compile(config: &RouteConfig) -> Result<Self, String> {
+        let matches = &config.matches;
+        let path_regex = match &matches.path_regex {
+            Some(pattern) => Some(Regex::new(pattern).map_err(|e| {
+                format!("Route '{}' has an invalid path_regex: {}", config.name, e)
+            })?),
+            None => None,
+        };
+
+        let action = match (&config.redirect, &config.direct_response) {
+            (Some(_), Some(_)) => {
+                return Err(format!(
+                    "Route '{}' cannot have both a redirect and a direct_response",
+                    config.name
+                ));
+            }
+            (Some(redirect), None) => RouteAction::Redirect {
+                status: redirect_status(redirect.status)
+                    .map_err(|e| format!("Route '{}': {}", config.name, e))?,
+                location: redirect.location.clone(),
+            },
+            (None, Some(direct)) => RouteAction::Direct {
+                status: direct_status(direct.status)
+                    .map_err(|e| format!("Route '{}': {}", config.name, e))?,
+                headers: direct_headers(&direct.headers)
+                    .map_err(|e| format!("Route '{}': {}", config.name, e))?,
+                body: direct.body.clone(),
+            },
+            (None, None) => RouteAction::Proxy,
+        };
+
+        let targets = [
+            !config.pool.is_empty(),
+            !config.split.is_empty(),
+            !matches!(action, RouteAction::Proxy),
+        ];
+        if targets.iter().filter(|set| **set).count() != 1 {
+            return Err(format!(
+                "Route '{}' needs exactly one of pool, split, redirect or direct_response",
+                config.name
+            ));
+        }
+
+        let split = if config.split.is_empty() {
+            None
+        } else {
+            Some(
+                Split::compile(&config.split, config.split_override.as_ref())
+                    .map_err(|e| format!("Route '{}': {}", config.name, e))?,
+            )
+        };
+
+        Ok(Route {
+            name: config.name.clone(),
+            host: matches.host.as_deref().map(HostMatch::parse),
+            path: matches.path.clone(),
+            path_prefix: matches.path_prefix.clone(),
+            path_regex,
+            methods: matches
+                .methods
+                .iter()
+                .map(|m| m.to_ascii_uppercase())
+                .collect(),
+            headers: matches
+                .headers
+                .iter()
+                .map(|(k, v)| (k.to_ascii_lowercase(), v.clone()))
+                .collect(),
+            query: matches
+                .query
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            pool: config.pool.clone(),
+            split,
+            action,
+            mirror: config
+                .mirror
+                .as_ref()
+                .map(Mirror::compile)
+                .transpose()
+                .map_err(|e| format!("Route '{}': {}", config.name, e))?,
+            rewrite: PathRewrite::compile(&config.rewrite)
+                .map_err(|e| format!("Route '{}': {}", config.name, e))?,
+            filters: FilterChain::compile(
+                &config.filters,
+                &config.request_headers,
+                &config.response_headers,
+            )
+            .map_err(|e| format!("Route '{}': {}", config.name, e))?,
+        })
+    }
+
+    // Sends everything to the default pool unchanged
+    fn catch_all() -> Self {
+        Route {
+            name: String::new(),
+            host: None,
+            path: None,
+            path_prefix: None,
+            path_regex: None,
+            methods: Vec::new(),
+            headers: Vec::new(),
+            query: Vec::new(),
+            pool: DEFAULT_POOL.to_string(),
+            split: None,
+            action: RouteAction::Proxy,
+            mirror: None,
+            rewrite: PathRewrite::default(),
+            filters: FilterChain::default(),
+        }
+    }
+
+    // Empty for the catch-all route used when no routes are configured
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Pool for this request; split routes place the client by `client_key`
+    pub fn select_pool<B>(&self, req: &Request<B>, client_key: &str) -> &str {
+        match &self.split {
+            Some(split) => split.select(req, client_key),
+            None => &self.pool,
+        }
+    }
+
+    fn matches<B>(&self, req: &Request<B>) -> bool {
+        if let Some(host_match) = &self.host {
+            match request_host(req) {
+                Some(host) if host_match.matches(&host) => {}
+                _ => return false,
+            }
+        }
+
+        let path = req.uri().path();
+        if self.path.as_ref().is_some_and(|p| p != path) {
+            return false;
+        }
+        if self
+            .path_prefix
+            .as_ref()
+            .is_some_and(|p| strip_path_prefix(path, p).is_none())
+        {
+            return false;
+        }
+        if self.path_regex.as_ref().is_some_and(|r| !r.is_match(path)) {
+            return false;
+        }
+
+        if !self.methods.is_empty() && !self.methods.iter().any(|m| m == req.method().as_str()) {
+            return false;
+        }
+
+        for (name, value) in &self.headers {
+            let matched = req
+                .headers()
+                .get_all(name.as_str())
+                .iter()
+                .any(|v| v.as_bytes() == value.as_bytes());
+            if !matched {
+                return false;
+            }
+        }
+
+        if !self.query.is_empty() {
+            let query = req.uri().query().unwrap_or("");
+            let params: Vec<(&str, &str)> = query
+                .split('&')
+                .filter_map(|param| param.split_once('='))
+                .collect();
+            for (name, value) in &self.query {
+                if !params.iter().any(|(k, v)| k == name && v == value) {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+// The rest of `path` after `prefix`, matching whole segments only: "/api"
+// covers "/api" and "/api/users" but not "/apiary". A prefix ending in '/'
+// matches anything that starts with it.
+pub fn strip_path_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let rest = path.strip_prefix(prefix)?;
+    (rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/')).then_some(rest)
+}
+
+// Only statuses that redirect with a Location; 300, 304 and the like do not
+pub fn redirect_status(status: u16) -> Result<StatusCode, String> {
+    match status {
+        301 | 302 | 307 | 308 => Ok(StatusCode::from_u16(status).unwrap()),
+        _ => Err(format!(
+            "redirect status must be 301, 302, 307 or 308, got {}",
+            status
+        )),
+    }
+}
+
+pub fn direct_status(status: u16) -> Result<StatusCode, String> {
+    StatusCode::from_u16(status).map_err(|_| format!("invalid direct_response status {}", status))
+}
+
+pub fn direct_headers(headers: &HashMap<String, String>) -> Result<HeaderMap, String> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) else {
+            return Err(format!("invalid direct_response header '{}'", name));
+        };
+        map.append(name, value);
+    }
+    Ok(map)
+}
+
+// Host from the Host header (HTTP/1.1) or the URI authority (HTTP/2 and
+// HTTP/3), lowercased and without the port.
+pub fn request_host<B>(req: &Request<B>) -> Option<String> {
+    let authority: Authority = match req.headers().get(HOST) {
+        Some(host) => host.to_str().ok()?.parse().ok()?,
+        None => req.uri().authority()?.clone(),
+    };
+
+    Some(authority.host().to_ascii_lowercase())
+}
+
+// Maps requests to named backend pools, evaluating routes in priority order
+pub struct Router {
+    routes: Vec<Route>,
+    default_route: Route,
+}
+
+impl Router {
+    pub fn new(config: &LoadBalancerConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut route_configs: Vec<&RouteConfig> = config.routes.iter().collect();
+        // Stable sort keeps config order among equal priorities
+        route_configs.sort_by_key(|r| std::cmp::Reverse(r.priority));
+
+        let pool_exists = |name: &str| name == DEFAULT_POOL || config.pools.contains_key(name);
+
+        let mut routes = Vec::new();
+        for route_config in route_configs {
+            if !route_config.pool.is_empty() && !pool_exists(&route_config.pool) {
+                return Err(format!(
+                    "Route '{}' points to unknown pool '{}'",
+                    route_config.name, route_config.pool
+                )
+                .into());
+            }
+            if let Some(target) = route_config
+                .split
+                .iter()
+                .find(|target| !pool_exists(&target.pool))
+            {
+                return Err(format!(
+                    "Route '{}' splits to unknown pool '{}'",
+                    route_config.name, target.pool
+                )
+                .into());
+            }
+            if let Some(mirror) = &route_config.mirror
+                && !pool_exists(&mirror.pool)
+            {
+                return Err(format!(
+                    "Route '{}' mirrors to unknown pool '{}'",
+                    route_config.name, mirror.pool
+                )
+                .into());
+            }
+            routes.push(Route::compile(route_config)?);
+        }
+
+        Ok(Router {
+            routes,
+            default_route: Route::catch_all(),
+        })
+    }
+
+    fn split(&self, route_name: &str) -> Result<&Split, String> {
+        let route = self
+            .routes
+            .iter()
+            .find(|route| !route.name.is_empty() && route.name == route_name)
+            .ok_or_else(|| format!("Unknown route '{}'", route_name))?;
+        route
+            .split
+            .as_ref()
+            .ok_or_else(|| format!("Route '{}' has no split", route_name))
+    }
+
+    pub fn split_weights(&self, route_name: &str) -> Result<Vec<(String, u32)>, String> {
+        Ok(self.split(route_name)?.weights())
+    }
+
+    // Changes one pool's share of a split route and returns the new weights
+    pub fn set_split_weight(
+        &self,
+        route_name: &str,
+        pool: &str,
+        weight: u32,
+    ) -> Result<Vec<(String, u32)>, String> {
+        let split = self.split(route_name)?;
+
+        split.set_weight(pool, weight)?;
+        info!(
+            "Set split weight of pool '{}' on route '{}' to {}",
+            pool, route_name, weight
+        );
+
+        Ok(split.weights())
+    }
+
+    // Carries split weights changed at runtime over from the router this
+    // one replaces, for routes whose split is unchanged in the config
+    pub fn keep_split_weights(&self, old: &Router) {
+        for route in &self.routes {
+            if let Some(split) = &route.split
+                && let Ok(old_split) = old.split(&route.name)
+            {
+                split.keep_weights(old_split);
+            }
+        }
+    }
+
+    // First matching route. Without any routes configured, everything goes
+    // to the default pool.
+    pub fn route<B>(&self, req: &Request<B>) -> Option<&Route> {
+        if self.routes.is_empty() {
+            return Some(&self.default_route);
+        }
+
+        let route = self.routes.iter().find(|route| route.matches(req))?;
+        if !route.name.is_empty() {
+            info!("Request matched route '{}'", route.name);
+        }
+
+        Some(route)
+    }
+}
+
+// The router in use. A reloaded config replaces it as a whole; requests
+// keep the router they started with.
+pub struct SharedRouter {
+    current: ArcSwap<Router>,
+}
+
+impl SharedRouter {
+    pub fn new(router: Router) -> Self {
+        SharedRouter {
+            current: ArcSwap::from_pointee(router),
+        }
+    }
+
+    pub fn load(&self) -> Arc<Router> {
+        self.current.load_full()
+    }
+
+    pub fn store(&self, router: Router) {
+        self.current.store(Arc::new(router));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router(routes: &[&str]) -> Router {
+        let config = LoadBalancerConfig {
+            routes: routes
+                .iter()
+                .map(|json| serde_json::from_str(json).unwrap())
+                .collect(),
+            ..Default::default()
+        };
+        Router::new(&config).unwrap()
+    }
+
+    fn request(method: &str, uri: &str, headers: &[(&str, &str)]) -> Request<()> {
+        let mut builder = Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(()).unwrap()
+    }
+
+    // Name of the route `req` matches, if any
+    fn matched(router: &Router, req: &Request<()>) -> Option<String> {
+        router.route(req).map(|route| route.name().to_string())
+    }
+
+    #[test]
+    fn everything_goes_to_default_without_routes() {
+        let router = router(&[]);
+        let req = request("GET", "/anything", &[]);
+        let route = router.route(&req).unwrap();
+        assert_eq!(route.select_pool(&req, "10.0.0.1"), DEFAULT_POOL);
+    }
+
+    #[test]
+    fn matches_hosts_and_wildcards() {
+        let router = router(&[
+            r#"{ "name": "exact", "match": { "host": "api.example.com" }, "pool": "default" }"#,
+            r#"{ "name": "wildcard", "match": { "host": "*.example.com" }, "pool": "default" }"#,
+        ]);
+        let get = |host: &str| matched(&router, &request("GET", "/", &[("host", host)]));
+
+        assert_eq!(get("api.example.com").as_deref(), Some("exact"));
+        assert_eq!(get("API.Example.com:8080").as_deref(), Some("exact"));
+        assert_eq!(get("www.example.com").as_deref(), Some("wildcard"));
+        // The wildcard needs a subdomain
+        assert_eq!(get("example.com"), None);
+        assert_eq!(get("badexample.com"), None);
+
+        // HTTP/2 requests carry the host in the URI
+        let req = request("GET", "https://www.example.com/", &[]);
+        assert_eq!(matched(&router, &req).as_deref(), Some("wildcard"));
+    }
+
+    #[test]
+    fn matches_methods_headers_and_query() {
+        let router = router(&[r#"{
+            "name": "writes",
+            "match": {
+                "methods": ["post", "PUT"],
+                "headers": { "X-Tenant": "blue" },
+                "query": { "debug": "1" }
+            },
+            "pool": "default"
+        }"#]);
+        let hit = |method: &str, uri: &str, headers: &[(&str, &str)]| {
+            matched(&router, &request(method, uri, headers)).is_some()
+        };
+
+        assert!(hit("POST", "/?debug=1", &[("x-tenant", "blue")]));
+        assert!(hit("PUT", "/?a=b&debug=1", &[("x-tenant", "blue")]));
+        assert!(!hit("GET", "/?debug=1", &[("x-tenant", "blue")]));
+        assert!(!hit("POST", "/?debug=1", &[("x-tenant", "green")]));
+        assert!(!hit("POST", "/?debug=10", &[("x-tenant", "blue")]));
+        assert!(!hit("POST", "/", &[("x-tenant", "blue")]));
+    }
+
+    #[test]
+    fn matches_paths() {
+        let router = router(&[
+            r#"{ "name": "exact", "match": { "path": "/health" }, "pool": "default" }"#,
+            r#"{ "name": "regex", "match": { "path_regex": "^/v[0-9]+/" }, "pool": "default" }"#,
+        ]);
+        let get = |uri: &str| matched(&router, &request("GET", uri, &[]));
+
+        assert_eq!(get("/health").as_deref(), Some("exact"));
+        assert_eq!(get("/health/deep"), None);
+        assert_eq!(get("/v2/users").as_deref(), Some("regex"));
+        assert_eq!(get("/vx/users"), None);
+    }
+
+    #[test]
+    fn prefix_matches_whole_segments() {
+        assert_eq!(strip_path_prefix("/api", "/api"), Some(""));
+        assert_eq!(strip_path_prefix("/api/users", "/api"), Some("/users"));
+        assert_eq!(strip_path_prefix("/apiary", "/api"), None);
+        assert_eq!(strip_path_prefix("/apiary", "/api/"), None);
+        assert_eq!(strip_path_prefix("/api/users", "/api/"), Some("users"));
+        assert_eq!(strip_path_prefix("/anything", "/"), Some("anything"));
+
+        let router = router(&[r#"{ "match": { "path_prefix": "/api" }, "pool": "default" }"#]);
+        assert!(router.route(&request("GET", "/api/users", &[])).is_some());
+        assert!(router.route(&request("GET", "/apiary", &[])).is_none());
+    }
+
+    #[test]
+    fn higher_priority_wins_then_config_order() {
+        let router = router(&[
+            r#"{ "name": "first", "match": { "path_prefix": "/" }, "pool": "default" }"#,
+            r#"{ "name": "second", "match": { "path_prefix": "/" }, "pool": "default" }"#,
+            r#"{ "name": "api", "priority": 10, "match": { "path_prefix": "/api" }, "pool": "default" }"#,
+        ]);
+        let get = |uri: &str| matched(&router, &request("GET", uri, &[]));
+
+        assert_eq!(get("/api/users").as_deref(), Some("api"));
+        assert_eq!(get("/other").as_deref(), Some("first"));
+    }
+
+    #[test]
+    fn accepts_only_location_redirects() {
+        for status in [301, 302, 307, 308] {
+            assert!(redirect_status(status).is_ok());
+        }
+        for status in [200, 300, 304, 399] {
+            assert!(redirect_status(status).is_err());
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use hyper::Request;
+use hyper::header::{COOKIE, HeaderName};
+
+use crate::config::{SplitConfig, SplitOverrideConfig};
+
+// Resolution of the hash used to place clients along the split
+const SPLIT_BUCKETS: u64 = 10_000;
+
+struct SplitTarget {
+    pool: String,
+    // As given in the config
+    configured: u32,
+    // Adjustable at runtime through /admin/split
+    weight: AtomicU32,
+}
+
+// Divides a route's traffic between pools by weight, e.g. 95% stable and
+// 5% canary. Each client hashes to a fixed point, so it keeps seeing the
+// same pool while the weights stay the same.
+pub struct Split {
+    targets: Vec<SplitTarget>,
+    override_header: Option<HeaderName>,
+    override_cookie: Option<String>,
+}
+
+impl Split {
+    pub fn compile(
+        targets: &[SplitConfig],
+        overrides: Option<&SplitOverrideConfig>,
+    ) -> Result<Self, String> {
+        if targets.iter().all(|t| t.weight == 0) {
+            return Err("Split needs at least one pool with a weight above 0".to_string());
+        }
+        for (i, target) in targets.iter().enumerate() {
+            if targets[..i].iter().any(|t| t.pool == target.pool) {
+                return Err(format!("Split lists pool '{}' more than once", target.pool));
+            }
+        }
+
+        let override_header = match overrides.and_then(|o| o.header.as_deref()) {
+            Some(name) => Some(
+                HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| format!("Invalid split override header '{}'", name))?,
+            ),
+            None => None,
+        };
+
+        Ok(Split {
+            targets: targets
+                .iter()
+                .map(|t| SplitTarget {
+                    pool: t.pool.clone(),
+                    configured: t.weight,
+                    weight: AtomicU32::new(t.weight),
+                })
+                .collect(),
+            override_header,
+            override_cookie: overrides.and_then(|o| o.cookie.clone()),
+        })
+    }
+
+    pub fn set_weight(&self, pool: &str, weight: u32) -> Result<(), String> {
+        let target = self
+            .targets
+            .iter()
+            .find(|t| t.pool == pool)
+            .ok_or_else(|| format!("Pool '{}' is not part of this split", pool))?;
+        target.weight.store(weight, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Takes over the weights set at runtime on `old`, the same route's split
+    // before a reload, unless the reloaded config changed the split
+    pub fn keep_weights(&self, old: &Split) {
+        let unchanged = self.targets.len() == old.targets.len()
+            && self
+                .targets
+                .iter()
+                .zip(&old.targets)
+                .all(|(new, old)| new.pool == old.pool && new.configured == old.configured);
+        if !unchanged {
+            return;
+        }
+        for (new, old) in self.targets.iter().zip(&old.targets) {
+            new.weight
+                .store(old.weight.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+
+    pub fn weights(&self) -> Vec<(String, u32)> {
+        self.targets
+            .iter()
+            .map(|t| (t.pool.clone(), t.weight.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    pub fn select<B>(&self, req: &Request<B>, client_key: &str) -> &str {
+        if let Some(pool) = self.forced_pool(req) {
+            return pool;
+        }
+
+        let weights: Vec<u64> = self
+            .targets
+            .iter()
+            .map(|t| u64::from(t.weight.load(Ordering::Relaxed)))
+            .collect();
+        let total: u64 = weights.iter().sum();
+        if total == 0 {
+            return &self.targets[0].pool;
+        }
+
+        let mut hasher = DefaultHasher::new();
+        client_key.hash(&mut hasher);
+        let point = (hasher.finish() % SPLIT_BUCKETS) * total / SPLIT_BUCKETS;
+
+        let mut upper = 0;
+        for (target, weight) in self.targets.iter().zip(weights) {
+            upper += weight;
+            if point < upper {
+                return &target.pool;
+            }
+        }
+
+        &self.targets[self.targets.len() - 1].pool
+    }
+
+    // Testers can pin a version by naming its pool in the override header
+    // or cookie; unknown names are ignored.
+    fn forced_pool<B>(&self, req: &Request<B>) -> Option<&str> {
+        if let Some(header) = &self.override_header
+            && let Some(value) = req.headers().get(header).and_then(|v| v.to_str().ok())
+            && let Some(target) = self.targets.iter().find(|t| t.pool == value.trim())
+        {
+            return Some(&target.pool);
+        }
+
+        let cookie_name = self.override_cookie.as_deref()?;
+        for header in req.headers().get_all(COOKIE) {
+            let Ok(cookies) = header.to_str() else {
+                continue;
+            };
+            for cookie in cookies.split(';') {
+                if let Some((name, value)) = cookie.trim().split_once('=')
+                    && name == cookie_name
+                    && let Some(target) = self.targets.iter().find(|t| t.pool == value)
+                {
+                    return Some(&target.pool);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(weights: &[(&str, u32)], overrides: Option<SplitOverrideConfig>) -> Split {
+        let targets: Vec<SplitConfig> = weights
+            .iter()
+            .map(|(pool, weight)| SplitConfig {
+                pool: pool.to_string(),
+                weight: *weight,
+            })
+            .collect();
+        Split::compile(&targets, overrides.as_ref()).unwrap()
+    }
+
+    fn request(header: Option<(&str, &str)>) -> Request<()> {
+        let mut builder = Request::builder().uri("/");
+        if let Some((name, value)) = header {
+            builder = builder.header(name, value);
+        }
+        builder.body(()).unwrap()
+    }
+
+    fn share(split: &Split, pool: &str) -> usize {
+        let req = request(None);
+        (0..10_000)
+            .filter(|i| split.select(&req, &format!("10.0.{}.{}", i / 256, i % 256)) == pool)
+            .count()
+    }
+
+    #[test]
+    fn divides_clients_by_weight() {
+        let split = split(&[("stable", 90), ("canary", 10)], None);
+        let canary = share(&split, "canary");
+        assert!((800..1_200).contains(&canary), "{}", canary);
+    }
+
+    #[test]
+    fn client_keeps_its_pool() {
+        let split = split(&[("stable", 50), ("canary", 50)], None);
+        let req = request(None);
+        let first = split.select(&req, "10.0.0.7").to_string();
+        for _ in 0..10 {
+            assert_eq!(split.select(&req, "10.0.0.7"), first);
+        }
+    }
+
+    #[test]
+    fn zero_weight_gets_nothing() {
+        let split = split(&[("stable", 1), ("canary", 0)], None);
+        assert_eq!(share(&split, "canary"), 0);
+
+        split.set_weight("canary", 1).unwrap();
+        split.set_weight("stable", 0).unwrap();
+        assert_eq!(share(&split, "canary"), 10_000);
+        assert!(split.set_weight("other", 1).is_err());
+    }
+
+    #[test]
+    fn override_header_and_cookie_pin_a_pool() {
+        let overrides = SplitOverrideConfig {
+            header: Some("x-version".to_string()),
+            cookie: Some("version".to_string()),
+        };
+        let split = split(&[("stable", 1), ("canary", 0)], Some(overrides));
+
+        let req = request(Some(("x-version", "canary")));
+        assert_eq!(split.select(&req, "10.0.0.1"), "canary");
+        let req = request(Some(("cookie", "a=b; version=canary")));
+        assert_eq!(split.select(&req, "10.0.0.1"), "canary");
+        // Unknown pools are ignored
+        let req = request(Some(("x-version", "nope")));
+        assert_eq!(split.select(&req, "10.0.0.1"), "stable");
+    }
+
+    #[test]
+    fn rejects_bad_config() {
+        let targets = [SplitConfig {
+            pool: "stable".to_string(),
+            weight: 0,
+        }];
+        assert!(Split::compile(&targets, None).is_err());
+
+        let targets = [SplitConfig {
+            pool: "stable".to_string(),
+            weight: 1,
+        }];
+        let overrides = SplitOverrideConfig {
+            header: Some("bad header".to_string()),
+            cookie: None,
+        };
+        assert!(Split::compile(&targets, Some(&overrides)).is_err());
+    }
+
+    #[test]
+    fn rejects_a_pool_listed_twice() {
+        let targets =
+            [("stable", 1), ("canary", 1), ("stable", 1)].map(|(pool, weight)| SplitConfig {
+                pool: pool.to_string(),
+                weight,
+            });
+        let error = Split::compile(&targets, None).err().unwrap();
+        assert!(error.contains("'stable'"), "{}", error);
+    }
+
+    #[test]
+    fn keeps_runtime_weights_while_config_is_unchanged() {
+        let old = split(&[("stable", 90), ("canary", 10)], None);
+        old.set_weight("canary", 50).unwrap();
+
+        let reloaded = split(&[("stable", 90), ("canary", 10)], None);
+        reloaded.keep_weights(&old);
+        assert_eq!(
+            reloaded.weights(),
+            [("stable".to_string(), 90), ("canary".to_string(), 50)]
+        );
+
+        // A changed split in the config wins over runtime changes
+        let reloaded = split(&[("stable", 80), ("canary", 20)], None);
+        reloaded.keep_weights(&old);
+        assert_eq!(
+            reloaded.weights(),
+            [("stable".to_string(), 80), ("canary".to_string(), 20)]
+        );
+    }
+}
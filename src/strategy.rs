@@ -0,0 +1,536 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwap;
+
+use crate::config::{SlowStartConfig, SlowStartCurve, Strategy};
+use crate::load_balancer::{Backend, Sessions};
+
+// Longest precomputed weighted schedule; larger weights are scaled down
+const MAX_SCHEDULE_LEN: u64 = 1024;
+
+// How a pool picks backends. The pool decides which backends may take a
+// request (health, draining, priority tier, zone, slow start) and the
+// strategy picks among them. Strategies are shared by concurrent requests,
+// so any state they keep needs interior mutability.
+//
+// Custom strategies are installed with `Pool::set_balancing_strategy`.
+pub trait BalancingStrategy: Send + Sync {
+    // Picks the backend for a request, or None if no backend can take it
+    fn select(&self, selection: &Selection<'_>) -> Option<Arc<Backend>>;
+
+    // The pool's backends or their weights changed; `backends` is the new
+    // set, in the order `Selection::backends` will list them
+    fn on_backends_change(&self, _backends: &[Arc<Backend>]) {}
+
+    // A request, connection or UDP flow was sent to `backend`
+    fn on_request_start(&self, _backend: &Backend) {}
+
+    // The backend answered, or failed to
+    fn on_response(&self, _backend: &Backend, _outcome: Outcome) {}
+
+    // The backend was marked healthy or unhealthy
+    fn on_health_change(&self, _backend: &Backend, _healthy: bool) {}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Outcome {
+    // Response headers (or, for TCP, the connection) arrived after `latency`
+    Success { latency: Duration },
+    Failure,
+}
+
+// The part of a pool a request may be served from
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Scope {
+    pub(crate) tier: u32,
+    // Some(true) for same-zone backends only, Some(false) for other zones
+    pub(crate) local: Option<bool>,
+}
+
+// What a strategy picks from: the pool's backends and the request's client
+pub struct Selection<'a> {
+    pub backends: &'a [Arc<Backend>],
+    // Client address, for strategies with affinity; None for gRPC calls
+    // and mirrored requests, which are balanced one by one
+    pub client: Option<&'a str>,
+    pub sessions: &'a Sessions,
+    scope: Scope,
+    slow_start: Option<&'a SlowStartConfig>,
+    now: Instant,
+}
+
+impl<'a> Selection<'a> {
+    pub(crate) fn new(
+        backends: &'a [Arc<Backend>],
+        client: Option<&'a str>,
+        sessions: &'a Sessions,
+        scope: Scope,
+        slow_start: Option<&'a SlowStartConfig>,
+    ) -> Self {
+        Selection {
+            backends,
+            client,
+            sessions,
+            scope,
+            slow_start,
+            now: Instant::now(),
+        }
+    }
+
+    // Whether the backend may take this request at all
+    pub fn is_available(&self, backend: &Backend) -> bool {
+        backend.takes_traffic()
+            && backend.priority == self.scope.tier
+            && self.scope.local.is_none_or(|local| backend.local == local)
+    }
+
+    // Share of its weight a backend gets right now: ramps from the slow
+    // start minimum up to 1.0 over the window after it became healthy
+    pub fn warmup(&self, backend: &Backend) -> f64 {
+        let (Some(slow_start), Some(since)) = (self.slow_start, backend.healthy_since()) else {
+            return 1.0;
+        };
+        if slow_start.window_seconds == 0 {
+            return 1.0;
+        }
+
+        let progress = self.now.saturating_duration_since(since).as_secs_f64()
+            / slow_start.window_seconds as f64;
+        if progress >= 1.0 {
+            return 1.0;
+        }
+
+        let min = (slow_start.min_weight_percent / 100.0).clamp(0.01, 1.0);
+        match slow_start.curve {
+            SlowStartCurve::Linear => min + (1.0 - min) * progress,
+            SlowStartCurve::Exponential => min * (1.0 / min).powf(progress),
+        }
+    }
+
+    // Takes the first available backend from `order` (indexes into
+    // `backends`), passing over warming backends part of the time. Returns
+    // its position in `order` along with it.
+    pub fn first_available(
+        &self,
+        order: impl IntoIterator<Item = usize>,
+    ) -> Option<(usize, Arc<Backend>)> {
+        let mut skipped = None;
+        for (position, idx) in order.into_iter().enumerate() {
+            let Some(backend) = self.backends.get(idx) else {
+                continue;
+            };
+            if !self.is_available(backend) {
+                continue;
+            }
+
+            let factor = self.warmup(backend);
+            if factor >= 1.0 || fastrand::f64() < factor {
+                return Some((position, backend.clone()));
+            }
+            skipped.get_or_insert((position, idx));
+        }
+
+        // Only warming backends are left; still better than nothing
+        skipped.map(|(position, idx)| (position, self.backends[idx].clone()))
+    }
+}
+
+// Builds the built-in strategy for a configured `strategy`
+pub fn from_config(strategy: &Strategy) -> Arc<dyn BalancingStrategy> {
+    match strategy {
+        Strategy::RoundRobin => Arc::new(RoundRobin::default()),
+        Strategy::WeightedRoundRobin => Arc::new(WeightedRoundRobin::default()),
+        Strategy::StickySession => Arc::new(StickySession::default()),
+    }
+}
+
+// Walks `len` slots from a shared cursor. The cursor also skips the slots
+// passed over, so unavailable backends do not hand their turns to a single
+// neighbour.
+fn next_in_cycle(
+    cursor: &AtomicUsize,
+    len: usize,
+    selection: &Selection<'_>,
+    slot: impl Fn(usize) -> usize,
+) -> Option<Arc<Backend>> {
+    if len == 0 {
+        return None;
+    }
+
+    let start = cursor.fetch_add(1, Ordering::Relaxed);
+    let order = (0..len).map(|offset| slot(start.wrapping_add(offset) % len));
+    let (offset, backend) = selection.first_available(order)?;
+    if offset > 0 {
+        cursor.fetch_add(offset, Ordering::Relaxed);
+    }
+
+    Some(backend)
+}
+
+// Each available backend in turn
+#[derive(Default)]
+pub struct RoundRobin {
+    cursor: AtomicUsize,
+}
+
+impl BalancingStrategy for RoundRobin {
+    fn select(&self, selection: &Selection<'_>) -> Option<Arc<Backend>> {
+        next_in_cycle(&self.cursor, selection.backends.len(), selection, |i| i)
+    }
+}
+
+// Smooth weighted round robin. The sequence is worked out once per change
+// to the backends instead of on every request.
+#[derive(Default)]
+pub struct WeightedRoundRobin {
+    // Indexes into the backends, interleaved by weight
+    schedule: ArcSwap<Vec<usize>>,
+    cursor: AtomicUsize,
+}
+
+impl WeightedRoundRobin {
+    fn build_schedule(backends: &[Arc<Backend>]) -> Vec<usize> {
+        // With every weight at zero the backends share the load evenly
+        // rather than the pool going dark
+        let total: u64 = backends.iter().map(|b| u64::from(b.weight)).sum();
+        if total == 0 {
+            return (0..backends.len()).collect();
+        }
+
+        let mut weights: Vec<u64> = backends
+            .iter()
+            .map(|b| match u64::from(b.weight) {
+                0 => 0,
+                weight if total <= MAX_SCHEDULE_LEN => weight,
+                weight => (weight * MAX_SCHEDULE_LEN / total).max(1),
+            })
+            .collect();
+        let divisor = weights.iter().fold(0, |a, &b| gcd(a, b));
+        if divisor > 1 {
+            weights.iter_mut().for_each(|w| *w /= divisor);
+        }
+
+        let len: u64 = weights.iter().sum();
+        let mut current = vec![0i64; weights.len()];
+        let mut schedule = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            let mut best = None;
+            for (i, &weight) in weights.iter().enumerate() {
+                if weight == 0 {
+                    continue;
+                }
+                current[i] += weight as i64;
+                if best.is_none_or(|b: usize| current[i] > current[b]) {
+                    best = Some(i);
+                }
+            }
+            let Some(best) = best else {
+                break;
+            };
+            current[best] -= len as i64;
+            schedule.push(best);
+        }
+
+        schedule
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+impl BalancingStrategy for WeightedRoundRobin {
+    fn select(&self, selection: &Selection<'_>) -> Option<Arc<Backend>> {
+        let schedule = self.schedule.load();
+        next_in_cycle(&self.cursor, schedule.len(), selection, |i| schedule[i])
+    }
+
+    fn on_backends_change(&self, backends: &[Arc<Backend>]) {
+        self.schedule
+            .store(Arc::new(Self::build_schedule(backends)));
+    }
+}
+
+// Keeps each client on the backend it was first sent to, for as long as
+// that backend may take its requests. A client moved to a fallback tier or
+// zone returns once the preferred backends recover. New clients are spread by weight.
+#[derive(Default)]
+pub struct StickySession {
+    weighted: WeightedRoundRobin,
+}
+
+impl BalancingStrategy for StickySession {
+    fn select(&self, selection: &Selection<'_>) -> Option<Arc<Backend>> {
+        let Some(client) = selection.client else {
+            return self.weighted.select(selection);
+        };
+
+        if let Some(backend_url) = selection.sessions.lookup(client)
+            && let Some(backend) = selection.backends.iter().find(|b| b.url == backend_url)
+            && selection.is_available(backend)
+        {
+            return Some(backend.clone());
+        }
+
+        let backend = self.weighted.select(selection)?;
+        selection.sessions.insert(client, &backend.url);
+        Some(backend)
+    }
+
+    fn on_backends_change(&self, backends: &[Arc<Backend>]) {
+        self.weighted.on_backends_change(backends);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BackendConfig, BackendProtocol, LoadBalancerConfig};
+    use crate::load_balancer::Pool;
+
+    // Backends given as (url, priority); one failure marks them unhealthy
+    fn pool(strategy: Strategy, backends: &[(&str, u32)]) -> Pool {
+        let configs: Vec<BackendConfig> = backends
+            .iter()
+            .map(|(url, priority)| BackendConfig {
+                url: url.to_string(),
+                weight: None,
+                protocol: BackendProtocol::Http1,
+                priority: *priority,
+                zone: None,
+            })
+            .collect();
+        let mut health_check = LoadBalancerConfig::default().health_check;
+        health_check.max_failures = 1;
+        Pool::from_config(&strategy, &configs, health_check, 300).unwrap()
+    }
+
+    fn next(pool: &Pool, client: &str) -> String {
+        pool.get_next_backend(Some(client)).unwrap().url.clone()
+    }
+
+    #[test]
+    fn sticky_client_fails_back_to_recovered_tier() {
+        let pool = pool(
+            Strategy::StickySession,
+            &[("http://a", 0), ("http://dr", 1)],
+        );
+        let primary = pool.snapshot().backends[0].clone();
+        assert_eq!(next(&pool, "10.0.0.1"), "http://a");
+
+        pool.mark_unhealthy(&primary);
+        assert_eq!(next(&pool, "10.0.0.1"), "http://dr");
+        assert_eq!(next(&pool, "10.0.0.1"), "http://dr");
+
+        // The backup is still healthy, but no longer in the tier in use
+        pool.mark_healthy(&primary);
+        assert_eq!(next(&pool, "10.0.0.1"), "http://a");
+        assert_eq!(next(&pool, "10.0.0.1"), "http://a");
+    }
+
+    // Marks the backend down and up again, so that it starts warming
+    fn recover(pool: &Pool, backend: &Backend) -> Instant {
+        pool.mark_unhealthy(backend);
+        pool.mark_healthy(backend);
+        backend.healthy_since().unwrap()
+    }
+
+    fn slow_start(curve: SlowStartCurve, window_seconds: u64) -> SlowStartConfig {
+        SlowStartConfig {
+            window_seconds,
+            min_weight_percent: 10.0,
+            curve,
+        }
+    }
+
+    // A selection from all of `pool` as seen at `now`
+    fn selection<'a>(
+        pool: &'a Pool,
+        backends: &'a [Arc<Backend>],
+        slow_start: &'a SlowStartConfig,
+        now: Instant,
+    ) -> Selection<'a> {
+        Selection {
+            backends,
+            client: None,
+            sessions: pool.sessions(),
+            scope: Scope {
+                tier: 0,
+                local: None,
+            },
+            slow_start: Some(slow_start),
+            now,
+        }
+    }
+
+    fn assert_near(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn linear_warmup() {
+        let pool = pool(Strategy::RoundRobin, &[("http://a", 0), ("http://b", 0)]);
+        let backends = pool.snapshot().backends.clone();
+        let since = recover(&pool, &backends[0]);
+        let config = slow_start(SlowStartCurve::Linear, 100);
+        let at = |seconds| {
+            selection(
+                &pool,
+                &backends,
+                &config,
+                since + Duration::from_secs(seconds),
+            )
+        };
+
+        assert_near(at(0).warmup(&backends[0]), 0.1);
+        assert_near(at(50).warmup(&backends[0]), 0.55);
+        assert_near(at(100).warmup(&backends[0]), 1.0);
+        assert_near(at(500).warmup(&backends[0]), 1.0);
+        // Backends healthy from the start take their full share at once
+        assert_near(at(0).warmup(&backends[1]), 1.0);
+    }
+
+    #[test]
+    fn exponential_warmup() {
+        let pool = pool(Strategy::RoundRobin, &[("http://a", 0)]);
+        let backends = pool.snapshot().backends.clone();
+        let since = recover(&pool, &backends[0]);
+        let config = slow_start(SlowStartCurve::Exponential, 100);
+        let at = |seconds| {
+            selection(
+                &pool,
+                &backends,
+                &config,
+                since + Duration::from_secs(seconds),
+            )
+        };
+
+        assert_near(at(0).warmup(&backends[0]), 0.1);
+        assert_near(at(50).warmup(&backends[0]), 0.1_f64.sqrt());
+        assert_near(at(100).warmup(&backends[0]), 1.0);
+    }
+
+    #[test]
+    fn zero_window_disables_warmup() {
+        let pool = pool(Strategy::RoundRobin, &[("http://a", 0)]);
+        let backends = pool.snapshot().backends.clone();
+        let since = recover(&pool, &backends[0]);
+        let config = slow_start(SlowStartCurve::Linear, 0);
+
+        let selection = selection(&pool, &backends, &config, since);
+        assert_near(selection.warmup(&backends[0]), 1.0);
+        assert_eq!(selection.first_available([0]).unwrap().0, 0);
+    }
+
+    #[test]
+    fn warming_backend_is_passed_over_in_proportion() {
+        fastrand::seed(7);
+        let pool = pool(Strategy::RoundRobin, &[("http://a", 0), ("http://b", 0)]);
+        let backends = pool.snapshot().backends.clone();
+        let since = recover(&pool, &backends[0]);
+        let config = slow_start(SlowStartCurve::Linear, 100);
+        let selection = selection(&pool, &backends, &config, since);
+
+        let warming = (0..10_000)
+            .filter(|_| selection.first_available([0, 1]).unwrap().0 == 0)
+            .count();
+        assert!((800..1_200).contains(&warming), "{}", warming);
+    }
+
+    #[test]
+    fn only_warming_backends_left() {
+        fastrand::seed(7);
+        let pool = pool(
+            Strategy::RoundRobin,
+            &[("http://a", 0), ("http://b", 0), ("http://c", 0)],
+        );
+        let backends = pool.snapshot().backends.clone();
+        pool.mark_unhealthy(&backends[0]);
+        recover(&pool, &backends[1]);
+        let since = recover(&pool, &backends[2]);
+        let config = slow_start(SlowStartCurve::Linear, 100);
+        let selection = selection(&pool, &backends, &config, since);
+
+        // Whichever warming backend comes first in the order is taken when
+        // both are passed over, never the unhealthy one
+        for _ in 0..1_000 {
+            let (position, backend) = selection.first_available([0, 1, 2]).unwrap();
+            assert!(position == 1 || position == 2);
+            assert_ne!(backend.url, "http://a");
+        }
+        let fallbacks = (0..10_000)
+            .filter(|_| selection.first_available([0, 1, 2]).unwrap().0 == 1)
+            .count();
+        // Taken on its own draw (~10%) or as the fallback (~81%)
+        assert!((8_700..9_500).contains(&fallbacks), "{}", fallbacks);
+    }
+
+    fn backends(weights: &[u32]) -> Vec<Arc<Backend>> {
+        let configs: Vec<BackendConfig> = weights
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| BackendConfig {
+                url: format!("http://backend-{}", i),
+                weight: Some(*weight),
+                protocol: BackendProtocol::Http1,
+                priority: 0,
+                zone: None,
+            })
+            .collect();
+        let health_check = LoadBalancerConfig::default().health_check;
+        Pool::from_config(&Strategy::WeightedRoundRobin, &configs, health_check, 300)
+            .unwrap()
+            .snapshot()
+            .backends
+            .clone()
+    }
+
+    fn counts(schedule: &[usize], backends: usize) -> Vec<usize> {
+        (0..backends)
+            .map(|i| schedule.iter().filter(|&&s| s == i).count())
+            .collect()
+    }
+
+    #[test]
+    fn schedule_interleaves_by_weight() {
+        let schedule = WeightedRoundRobin::build_schedule(&backends(&[5, 1, 1]));
+        assert_eq!(schedule, vec![0, 0, 1, 0, 2, 0, 0]);
+    }
+
+    #[test]
+    fn schedule_is_reduced_by_common_divisor() {
+        let schedule = WeightedRoundRobin::build_schedule(&backends(&[20, 40]));
+        assert_eq!(schedule.len(), 3);
+        assert_eq!(counts(&schedule, 2), vec![1, 2]);
+    }
+
+    #[test]
+    fn zero_weight_is_never_scheduled() {
+        let schedule = WeightedRoundRobin::build_schedule(&backends(&[3, 0, 2]));
+        assert_eq!(counts(&schedule, 3), vec![3, 0, 2]);
+    }
+
+    #[test]
+    fn all_zero_weights_share_evenly() {
+        let schedule = WeightedRoundRobin::build_schedule(&backends(&[0, 0, 0]));
+        assert_eq!(schedule, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn large_weights_are_scaled_down() {
+        let schedule = WeightedRoundRobin::build_schedule(&backends(&[1_000_000, 1, 999_999]));
+        assert!(schedule.len() as u64 <= MAX_SCHEDULE_LEN);
+
+        let counts = counts(&schedule, 3);
+        // The tiny weight keeps a slot rather than disappearing
+        assert_eq!(counts[1], 1);
+        assert!(counts[0].abs_diff(counts[2]) <= 1, "{:?}", counts);
+    }
+}
@@ -0,0 +1,302 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, timeout};
+
+use crate::config::TcpProxyConfig;
+use crate::load_balancer::{DEFAULT_POOL, LoadBalancer};
+use crate::strategy::Outcome;
+
+const BUFFER_SIZE: usize = 16 * 1024;
+
+pub async fn serve_tcp(
+    addr: SocketAddr,
+    lb: Arc<LoadBalancer>,
+    config: TcpProxyConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(addr).await?;
+
+    info!("Starting TCP proxy on {}", addr);
+
+    loop {
+        let (client, remote_addr) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to accept TCP connection: {}", e);
+                continue;
+            }
+        };
+
+        let lb = lb.clone();
+        let config = config.clone();
+        tokio::spawn(async move {
+            handle_connection(client, remote_addr, lb, config).await;
+        });
+    }
+}
+
+async fn handle_connection(
+    client: TcpStream,
+    remote_addr: SocketAddr,
+    lb: Arc<LoadBalancer>,
+    config: TcpProxyConfig,
+) {
+    let client_ip = remote_addr.ip().to_string();
+    let Some(pool) = lb.pool(DEFAULT_POOL) else {
+        return;
+    };
+    let Some(selected) = pool.get_next_backend(Some(&client_ip)) else {
+        error!(
+            "No healthy backends available for TCP connection from {}",
+            remote_addr
+        );
+        return;
+    };
+
+    let backend_url = &selected.url;
+    let Some(backend_addr) = selected.target.address() else {
+        error!("Backend {} has no host:port to connect to", backend_url);
+        return;
+    };
+
+    let connect_timeout = Duration::from_secs(config.connect_timeout_seconds);
+    let connect_started = Instant::now();
+    let backend = match timeout(connect_timeout, TcpStream::connect(&backend_addr)).await {
+        Ok(Ok(backend)) => backend,
+        Ok(Err(e)) => {
+            error!("Error connecting to backend {}: {}", backend_url, e);
+            pool.record_outcome(&selected, Outcome::Failure);
+            pool.mark_unhealthy(&selected);
+            return;
+        }
+        Err(_) => {
+            error!("Connecting to backend {} timed out", backend_url);
+            pool.record_outcome(&selected, Outcome::Failure);
+            pool.mark_unhealthy(&selected);
+            return;
+        }
+    };
+
+    let _ = client.set_nodelay(true);
+    let _ = backend.set_nodelay(true);
+
+    let latency = connect_started.elapsed();
+    pool.record_outcome(&selected, Outcome::Success { latency });
+    let _in_flight = pool.in_flight(&selected);
+    info!(
+        "Proxying TCP connection from {} to {} ({} active)",
+        remote_addr,
+        backend_url,
+        selected.active_connections()
+    );
+
+    let idle_timeout = Duration::from_secs(config.idle_timeout_seconds);
+    match copy_bidirectional_with_idle_timeout(client, backend, idle_timeout).await {
+        Ok((sent, received)) => info!(
+            "TCP connection from {} to {} closed ({} bytes sent, {} bytes received)",
+            remote_addr, backend_url, sent, received
+        ),
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => info!(
+            "TCP connection from {} to {} idle for {:?}, closing",
+            remote_addr, backend_url, idle_timeout
+        ),
+        Err(e) => warn!(
+            "TCP connection from {} to {} failed: {}",
+            remote_addr, backend_url, e
+        ),
+    }
+}
+
+// Copies bytes in both directions until both sides have closed. Half-closes
+// are propagated, and the connection is dropped once neither side has sent
+// anything for `idle_timeout`.
+async fn copy_bidirectional_with_idle_timeout(
+    client: TcpStream,
+    backend: TcpStream,
+    idle_timeout: Duration,
+) -> io::Result<(u64, u64)> {
+    let (mut client_read, mut client_write) = client.into_split();
+    let (mut backend_read, mut backend_write) = backend.into_split();
+
+    let mut client_buf = vec![0u8; BUFFER_SIZE];
+    let mut backend_buf = vec![0u8; BUFFER_SIZE];
+    let mut sent = 0u64;
+    let mut received = 0u64;
+    let mut client_open = true;
+    let mut backend_open = true;
+
+    while client_open || backend_open {
+        tokio::select! {
+            result = client_read.read(&mut client_buf), if client_open => {
+                let n = result?;
+                if n == 0 {
+                    client_open = false;
+                    backend_write.shutdown().await?;
+                } else {
+                    backend_write.write_all(&client_buf[..n]).await?;
+                    sent += n as u64;
+                }
+            }
+            result = backend_read.read(&mut backend_buf), if backend_open => {
+                let n = result?;
+                if n == 0 {
+                    backend_open = false;
+                    client_write.shutdown().await?;
+                } else {
+                    client_write.write_all(&backend_buf[..n]).await?;
+                    received += n as u64;
+                }
+            }
+            _ = sleep(idle_timeout) => {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "idle timeout"));
+            }
+        }
+    }
+
+    Ok((sent, received))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        BackendConfig, BackendProtocol, HealthCheckType, ListenerMode, LoadBalancerConfig,
+    };
+    use crate::load_balancer::Backend;
+
+    fn load_balancer(backend_addr: SocketAddr) -> Arc<LoadBalancer> {
+        let mut config = LoadBalancerConfig {
+            mode: ListenerMode::Tcp,
+            backends: vec![BackendConfig {
+                url: format!("tcp://{}", backend_addr),
+                weight: None,
+                protocol: BackendProtocol::Http1,
+                priority: 0,
+                zone: None,
+            }],
+            ..Default::default()
+        };
+        config.health_check.check_type = HealthCheckType::Tcp;
+        config.health_check.max_failures = 1;
+        Arc::new(LoadBalancer::new(config).unwrap())
+    }
+
+    fn backend(lb: &LoadBalancer) -> Arc<Backend> {
+        lb.pool(DEFAULT_POOL).unwrap().snapshot().backends[0].clone()
+    }
+
+    // Proxies one client connection through `lb` and returns the client end
+    async fn connect(lb: &Arc<LoadBalancer>) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap());
+        let (client, accepted) = tokio::join!(client, listener.accept());
+        let (accepted, remote_addr) = accepted.unwrap();
+        tokio::spawn(handle_connection(
+            accepted,
+            remote_addr,
+            lb.clone(),
+            TcpProxyConfig::default(),
+        ));
+        client.unwrap()
+    }
+
+    // Echoes each connection back until the client half-closes
+    async fn echo_backend() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut read, mut write) = stream.split();
+                    let _ = tokio::io::copy(&mut read, &mut write).await;
+                });
+            }
+        });
+        addr
+    }
+
+    async fn wait_for(condition: impl Fn() -> bool) {
+        for _ in 0..100 {
+            if condition() {
+                return;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition not met within a second");
+    }
+
+    #[tokio::test]
+    async fn relays_bytes_both_ways() {
+        let lb = load_balancer(echo_backend().await);
+        let mut client = connect(&lb).await;
+
+        client.write_all(b"ping").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut echoed = Vec::new();
+        client.read_to_end(&mut echoed).await.unwrap();
+        assert_eq!(echoed, b"ping");
+    }
+
+    #[tokio::test]
+    async fn counts_open_connections() {
+        let lb = load_balancer(echo_backend().await);
+        let backend = backend(&lb);
+
+        let mut first = connect(&lb).await;
+        let second = connect(&lb).await;
+        // A round trip proves the first connection reached the backend
+        first.write_all(b"x").await.unwrap();
+        first.read_exact(&mut [0u8; 1]).await.unwrap();
+        wait_for(|| backend.active_connections() == 2).await;
+
+        drop(first);
+        drop(second);
+        wait_for(|| backend.active_connections() == 0).await;
+    }
+
+    #[tokio::test]
+    async fn unreachable_backend_is_marked_unhealthy() {
+        // Bound and closed again, so nothing listens there
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let lb = load_balancer(addr);
+        let backend = backend(&lb);
+
+        let mut client = connect(&lb).await;
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        assert!(buf.is_empty());
+        assert!(!backend.is_healthy());
+        assert_eq!(backend.active_connections(), 0);
+    }
+
+    #[tokio::test]
+    async fn idle_connections_time_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (client, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        let (backend, backend_peer) = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (backend, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+            (backend.unwrap(), accepted.unwrap().0)
+        };
+
+        let result = copy_bidirectional_with_idle_timeout(
+            accepted.unwrap().0,
+            backend,
+            Duration::from_millis(50),
+        )
+        .await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        drop((client, backend_peer));
+    }
+}
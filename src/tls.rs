@@ -0,0 +1,122 @@
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use hyper::header::HeaderValue;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use log::{error, info, warn};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+use crate::client::BackendClients;
+use crate::config::TlsConfig;
+use crate::http3;
+use crate::load_balancer::LoadBalancer;
+use crate::load_balancer::service::handle_request;
+use crate::router::SharedRouter;
+
+// Reads a PEM certificate chain and its private key
+pub fn load_cert_and_key(
+    cert_path: &str,
+    key_path: &str,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), Box<dyn std::error::Error>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key_path)?))?
+        .ok_or_else(|| format!("No private key found in {}", key_path))?;
+
+    Ok((certs, key))
+}
+
+pub fn load_server_config(
+    config: &TlsConfig,
+) -> Result<Arc<ServerConfig>, Box<dyn std::error::Error>> {
+    let (certs, key) = load_cert_and_key(&config.cert_path, &config.key_path)?;
+
+    let mut server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+
+    // Offer h2 first so capable clients multiplex over one connection
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(Arc::new(server_config))
+}
+
+pub async fn serve_https(
+    addr: SocketAddr,
+    server_config: Arc<ServerConfig>,
+    lb: Arc<LoadBalancer>,
+    router: Arc<SharedRouter>,
+    clients: BackendClients,
+    alt_svc: Option<HeaderValue>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(addr).await?;
+    let acceptor = TlsAcceptor::from(server_config);
+
+    info!("Starting HTTPS listener on {}", addr);
+
+    loop {
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to accept TLS connection: {}", e);
+                continue;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+
+        let acceptor = acceptor.clone();
+        let lb = lb.clone();
+        let router = router.clone();
+        let clients = clients.clone();
+        let alt_svc = alt_svc.clone();
+
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("TLS handshake with {} failed: {}", remote_addr, e);
+                    return;
+                }
+            };
+
+            let is_h2 = stream.get_ref().1.alpn_protocol() == Some(b"h2");
+
+            let service = service_fn(move |req| {
+                let response = handle_request(
+                    req,
+                    lb.clone(),
+                    router.clone(),
+                    clients.clone(),
+                    remote_addr,
+                );
+                let alt_svc = alt_svc.clone();
+
+                async move {
+                    let mut response = response.await?;
+                    http3::advertise(response.headers_mut(), alt_svc.as_ref());
+                    Ok::<_, Infallible>(response)
+                }
+            });
+
+            let mut http = Http::new();
+            if is_h2 {
+                http.http2_only(true);
+            } else {
+                http.http1_only(true);
+            }
+
+            if let Err(e) = http.serve_connection(stream, service).await {
+                warn!("Error serving TLS connection from {}: {}", remote_addr, e);
+            }
+        });
+    }
+}
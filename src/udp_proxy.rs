@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tokio::time::interval;
+
+use crate::config::UdpProxyConfig;
+use crate::load_balancer::{Backend, DEFAULT_POOL, LoadBalancer};
+use crate::strategy::Outcome;
+
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+// One client source address pinned to one backend. Replies come back on a
+// dedicated upstream socket, so they can be matched to the client.
+struct Flow {
+    backend: Arc<Backend>,
+    upstream: Arc<UdpSocket>,
+    last_seen: Arc<std::sync::Mutex<Instant>>,
+    reply_task: JoinHandle<()>,
+}
+
+impl Flow {
+    fn touch(&self) {
+        *self.last_seen.lock().unwrap() = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_seen.lock().unwrap().elapsed()
+    }
+}
+
+pub async fn serve_udp(
+    addr: SocketAddr,
+    lb: Arc<LoadBalancer>,
+    config: UdpProxyConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let socket = UdpSocket::bind(addr).await?;
+    info!("Starting UDP proxy on {}", addr);
+    serve_socket(socket, lb, config).await;
+    Ok(())
+}
+
+async fn serve_socket(socket: UdpSocket, lb: Arc<LoadBalancer>, config: UdpProxyConfig) {
+    let socket = Arc::new(socket);
+    let idle_timeout = Duration::from_secs(config.flow_idle_timeout_seconds);
+    let mut sweep = interval(Duration::from_secs(1));
+    let mut flows: HashMap<SocketAddr, Flow> = HashMap::new();
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    // Set while new flows are being refused, so the limit is logged once
+    let mut at_limit = false;
+
+    loop {
+        tokio::select! {
+            result = socket.recv_from(&mut buf) => {
+                let (len, client_addr) = match result {
+                    Ok(received) => received,
+                    Err(e) => {
+                        warn!("Failed to receive UDP datagram: {}", e);
+                        continue;
+                    }
+                };
+
+                let full = flows.len() >= config.max_flows;
+                let flow = match flows.entry(client_addr) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(_) if full => {
+                        if !at_limit {
+                            warn!(
+                                "UDP flow limit of {} reached, dropping datagrams from new clients",
+                                config.max_flows
+                            );
+                            at_limit = true;
+                        }
+                        continue;
+                    }
+                    Entry::Vacant(entry) => match open_flow(client_addr, &socket, &lb).await {
+                        Some(flow) => entry.insert(flow),
+                        None => continue,
+                    },
+                };
+                flow.touch();
+                if let Err(e) = flow.upstream.send(&buf[..len]).await {
+                    warn!(
+                        "Failed to forward datagram from {} to {}: {}",
+                        client_addr, flow.backend.url, e
+                    );
+                }
+            }
+            _ = sweep.tick() => {
+                expire_flows(&mut flows, &lb, idle_timeout);
+                if at_limit && flows.len() < config.max_flows {
+                    info!("UDP flows below the limit again, accepting new clients");
+                    at_limit = false;
+                }
+            }
+        }
+    }
+}
+
+async fn open_flow(
+    client_addr: SocketAddr,
+    socket: &Arc<UdpSocket>,
+    lb: &Arc<LoadBalancer>,
+) -> Option<Flow> {
+    let client_ip = client_addr.ip().to_string();
+    let pool = lb.pool(DEFAULT_POOL)?;
+    let Some(backend) = pool.get_next_backend(Some(&client_ip)) else {
+        error!(
+            "No healthy backends available for UDP flow from {}",
+            client_addr
+        );
+        return None;
+    };
+
+    let Some(backend_addr) = backend.target.address() else {
+        error!("Backend {} has no host:port to send to", backend.url);
+        return None;
+    };
+
+    let bind_addr: SocketAddr = if client_addr.is_ipv4() {
+        "0.0.0.0:0".parse().unwrap()
+    } else {
+        "[::]:0".parse().unwrap()
+    };
+    let upstream = match UdpSocket::bind(bind_addr).await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            error!("Failed to bind upstream UDP socket: {}", e);
+            return None;
+        }
+    };
+    if let Err(e) = upstream.connect(&backend_addr).await {
+        error!("Error connecting UDP socket to {}: {}", backend.url, e);
+        pool.record_outcome(&backend, Outcome::Failure);
+        pool.mark_unhealthy(&backend);
+        return None;
+    }
+
+    let upstream = Arc::new(upstream);
+    let last_seen = Arc::new(std::sync::Mutex::new(Instant::now()));
+    let reply_task = tokio::spawn(relay_replies(
+        upstream.clone(),
+        socket.clone(),
+        client_addr,
+        backend.clone(),
+        last_seen.clone(),
+        lb.clone(),
+    ));
+
+    let active = pool.connection_opened(&backend);
+    info!(
+        "Opened UDP flow from {} to {} ({} active)",
+        client_addr, backend.url, active
+    );
+
+    Some(Flow {
+        backend,
+        upstream,
+        last_seen,
+        reply_task,
+    })
+}
+
+async fn relay_replies(
+    upstream: Arc<UdpSocket>,
+    socket: Arc<UdpSocket>,
+    client_addr: SocketAddr,
+    backend: Arc<Backend>,
+    last_seen: Arc<std::sync::Mutex<Instant>>,
+    lb: Arc<LoadBalancer>,
+) {
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+
+    loop {
+        match upstream.recv(&mut buf).await {
+            Ok(len) => {
+                *last_seen.lock().unwrap() = Instant::now();
+                if let Err(e) = socket.send_to(&buf[..len], client_addr).await {
+                    warn!("Failed to return datagram to {}: {}", client_addr, e);
+                }
+            }
+            Err(e) => {
+                // An ICMP port unreachable surfaces here as ConnectionRefused
+                warn!("UDP backend {} failed: {}", backend.url, e);
+                if let Some(pool) = lb.pool(DEFAULT_POOL) {
+                    pool.mark_unhealthy(&backend);
+                }
+                return;
+            }
+        }
+    }
+}
+
+// Drops flows that have been idle too long or whose backend became
+// unhealthy; the client's next datagram then opens a fresh flow.
+fn expire_flows(
+    flows: &mut HashMap<SocketAddr, Flow>,
+    lb: &Arc<LoadBalancer>,
+    idle_timeout: Duration,
+) {
+    if flows.is_empty() {
+        return;
+    }
+
+    let Some(pool) = lb.pool(DEFAULT_POOL) else {
+        return;
+    };
+    let expired: Vec<SocketAddr> = flows
+        .iter()
+        .filter(|(_, flow)| {
+            flow.idle_for() >= idle_timeout
+                || flow.reply_task.is_finished()
+                || !flow.backend.is_healthy()
+        })
+        .map(|(client_addr, _)| *client_addr)
+        .collect();
+
+    for client_addr in expired {
+        if let Some(flow) = flows.remove(&client_addr) {
+            flow.reply_task.abort();
+            let active = pool.connection_closed(&flow.backend);
+            info!(
+                "Closed UDP flow from {} to {} ({} active)",
+                client_addr, flow.backend.url, active
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        BackendConfig, BackendProtocol, HealthCheckType, ListenerMode, LoadBalancerConfig,
+    };
+    use tokio::time::{sleep, timeout};
+
+    // Replies to every datagram with `name`
+    async fn named_backend(name: &'static str) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            while let Ok((_, peer)) = socket.recv_from(&mut buf).await {
+                let _ = socket.send_to(name.as_bytes(), peer).await;
+            }
+        });
+        addr
+    }
+
+    // Starts a proxy in front of `backends` and returns it with its address
+    async fn proxy(
+        backends: &[SocketAddr],
+        config: UdpProxyConfig,
+    ) -> (Arc<LoadBalancer>, SocketAddr) {
+        let mut lb_config = LoadBalancerConfig {
+            mode: ListenerMode::Udp,
+            backends: backends
+                .iter()
+                .map(|addr| BackendConfig {
+                    url: format!("udp://{}", addr),
+                    weight: None,
+                    protocol: BackendProtocol::Http1,
+                    priority: 0,
+                    zone: None,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        lb_config.health_check.check_type = HealthCheckType::None;
+        let lb = Arc::new(LoadBalancer::new(lb_config).unwrap());
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(serve_socket(socket, lb.clone(), config));
+        (lb, addr)
+    }
+
+    async fn client(proxy: SocketAddr) -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(proxy).await.unwrap();
+        socket
+    }
+
+    // The reply to one datagram, or None if none comes back
+    async fn ask(client: &UdpSocket) -> Option<String> {
+        client.send(b"hi").await.unwrap();
+        let mut buf = [0u8; 64];
+        let len = timeout(Duration::from_millis(300), client.recv(&mut buf))
+            .await
+            .ok()?
+            .ok()?;
+        Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+    }
+
+    fn active(lb: &LoadBalancer) -> u32 {
+        let pool = lb.pool(DEFAULT_POOL).unwrap();
+        pool.snapshot()
+            .backends
+            .iter()
+            .map(|b| b.active_connections())
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn clients_stick_to_their_backend() {
+        let backends = [named_backend("a").await, named_backend("b").await];
+        let (_lb, proxy) = proxy(&backends, UdpProxyConfig::default()).await;
+
+        let first = client(proxy).await;
+        let second = client(proxy).await;
+        let first_backend = ask(&first).await.unwrap();
+        let second_backend = ask(&second).await.unwrap();
+        assert_ne!(first_backend, second_backend);
+
+        for _ in 0..5 {
+            assert_eq!(ask(&first).await.unwrap(), first_backend);
+            assert_eq!(ask(&second).await.unwrap(), second_backend);
+        }
+    }
+
+    #[tokio::test]
+    async fn new_clients_are_dropped_at_the_flow_limit() {
+        let backends = [named_backend("a").await];
+        let config = UdpProxyConfig {
+            max_flows: 1,
+            ..Default::default()
+        };
+        let (lb, proxy) = proxy(&backends, config).await;
+
+        let first = client(proxy).await;
+        let second = client(proxy).await;
+        assert_eq!(ask(&first).await.as_deref(), Some("a"));
+        assert_eq!(ask(&second).await, None);
+        // The open flow is unaffected
+        assert_eq!(ask(&first).await.as_deref(), Some("a"));
+        assert_eq!(active(&lb), 1);
+    }
+
+    #[tokio::test]
+    async fn idle_flows_expire() {
+        let backends = [named_backend("a").await];
+        let config = UdpProxyConfig {
+            flow_idle_timeout_seconds: 1,
+            max_flows: 1,
+        };
+        let (lb, proxy) = proxy(&backends, config).await;
+
+        let first = client(proxy).await;
+        assert_eq!(ask(&first).await.as_deref(), Some("a"));
+        assert_eq!(active(&lb), 1);
+
+        // Idle for a second, then swept within the next one
+        for _ in 0..30 {
+            if active(&lb) == 0 {
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(active(&lb), 0);
+
+        // The slot is free for another client
+        let second = client(proxy).await;
+        assert_eq!(ask(&second).await.as_deref(), Some("a"));
+    }
+}
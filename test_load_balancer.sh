@@ -1,5 +1,19 @@
 #!/bin/bash
 
+# Proxied requests go to the main listener; strategy and weight changes go
+# to the admin listener, which needs a bearer token from its `tokens` list
+LB_URL=${LB_URL:-http://localhost:8080}
+ADMIN_URL=${ADMIN_URL:-http://localhost:9090}
+
+if [ -z "$ADMIN_TOKEN" ]; then
+  echo "Set ADMIN_TOKEN to a token of the load balancer's admin listener" >&2
+  exit 1
+fi
+
+admin() {
+  curl -s -H "Authorization: Bearer $ADMIN_TOKEN" "$ADMIN_URL$1"
+}
+
 # Function to send requests to the load balancer
 send_requests() {
   local num_requests=$1
@@ -18,7 +32,7 @@ send_requests() {
 change_strategy() {
   local strategy=$1
   echo "Changing load balancing strategy to $strategy..."
-  curl -s "http://localhost:8080/admin/strategy?type=$strategy"
+  admin "/admin/strategy?type=$strategy"
   echo -e "\n---------------------------"
 }
 
@@ -27,7 +41,7 @@ set_weight() {
   local backend=$1
   local weight=$2
   echo "Setting weight for $backend to $weight..."
-  curl -s "http://localhost:8080/admin/weight?backend=$backend&weight=$weight"
+  admin "/admin/weight?backend=$backend&weight=$weight"
   echo -e "\n---------------------------"
 }
 
@@ -36,23 +50,23 @@ set_weight() {
 # Test round-robin strategy
 echo "=== Testing Round Robin Strategy ==="
 change_strategy "roundrobin"
-send_requests 6 "http://localhost:8080/test"
+send_requests 6 "$LB_URL/test"
 
 # Test weighted round-robin strategy
 echo "=== Testing Weighted Round Robin Strategy ==="
 change_strategy "weighted"
-send_requests 10 "http://localhost:8080/test"
+send_requests 10 "$LB_URL/test"
 
 # Change weights
 echo "=== Changing Backend Weights ==="
 set_weight "localhost:9001" 10
 set_weight "localhost:9002" 2
 set_weight "localhost:9003" 1
-send_requests 10 "http://localhost:8080/test"
+send_requests 10 "$LB_URL/test"
 
 # Test sticky sessions
 echo "=== Testing Sticky Sessions ==="
 change_strategy "sticky"
-send_requests 5 "http://localhost:8080/test"
+send_requests 5 "$LB_URL/test"
 
 echo "Test completed."